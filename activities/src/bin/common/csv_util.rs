@@ -0,0 +1,166 @@
+//! Reading and writing CSV data, shared by the p2 contact managers.
+//!
+//! Include it from a binary with:
+//!
+//! ```ignore
+//! #[path = "common/csv_util.rs"]
+//! mod csv_util;
+//! ```
+
+use thiserror::Error;
+
+/// Errors that may occur while reading CSV data.
+#[derive(Error, Debug, PartialEq)]
+pub enum CsvError {
+    #[error("quoted field is never closed")]
+    UnterminatedQuote,
+    #[error("unexpected {0:?} after closing quote")]
+    TrailingCharacter(char),
+}
+
+/// A single row read from CSV data.
+#[derive(Debug)]
+pub struct CsvRow {
+    /// Line number the row starts on (starting from 1).
+    pub line: usize,
+    /// The raw text of the row, used when reporting errors.
+    pub text: String,
+    /// The unquoted fields of the row.
+    pub fields: Result<Vec<String>, CsvError>,
+}
+
+/// Reads CSV data as described in RFC 4180.
+///
+/// Fields may be wrapped in double quotes, in which case they can contain
+/// commas, line breaks and quotes (written twice: `""`). Rows may end with
+/// either `\n` or `\r\n`, a UTF-8 byte order mark at the start is ignored
+/// and blank lines are skipped.
+///
+/// A quoted field that is never closed is reported on the line where its
+/// quote opened, and the rows after that line are read as usual.
+pub fn read_csv(data: &str) -> Vec<CsvRow> {
+    // Spreadsheet programs like to put a byte order mark at the start of
+    // the file. It isn't part of the first field, so we strip it.
+    let data = data.strip_prefix('\u{feff}').unwrap_or(data);
+
+    let mut rows = Vec::new();
+    // Byte position where the current row starts.
+    let mut start = 0;
+    let mut line = 1;
+
+    while start < data.len() {
+        let row_line = line;
+        // "char_indices" gives us each character along with its byte position
+        // in the rest of the data, which we use to slice out the raw text of
+        // the row. "peekable" lets us look at the next character without
+        // consuming it, which is needed to tell an escaped quote ("") apart
+        // from a closing quote.
+        let mut chars = data[start..].char_indices().peekable();
+        let mut end = data.len();
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut error = None;
+        // Whether we are inside a quoted field, and whether the current
+        // field was quoted and has already been closed.
+        let mut in_quotes = false;
+        let mut closed = false;
+        // Byte position and line number of the last opening quote.
+        let mut quote = (start, line);
+
+        while let Some((i, c)) = chars.next() {
+            if in_quotes {
+                if c == '"' {
+                    if let Some((_, '"')) = chars.peek() {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        in_quotes = false;
+                        closed = true;
+                    }
+                } else {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+                continue;
+            }
+            match c {
+                ',' => {
+                    fields.push(std::mem::take(&mut field));
+                    closed = false;
+                }
+                '\r' | '\n' => {
+                    // A "\r\n" pair ends the row just like a single "\n".
+                    if c == '\r' {
+                        if let Some((_, '\n')) = chars.peek() {
+                            chars.next();
+                        }
+                    }
+                    line += 1;
+                    end = start + i;
+                    break;
+                }
+                '"' if field.is_empty() && !closed => {
+                    in_quotes = true;
+                    quote = (start + i, line);
+                }
+                _ if closed => {
+                    // Only the first problem in a row is reported.
+                    error.get_or_insert(CsvError::TrailingCharacter(c));
+                }
+                _ => field.push(c),
+            }
+        }
+        let mut next = chars.peek().map_or(data.len(), |&(i, _)| start + i);
+
+        if in_quotes {
+            // The quoted field ran to the end of the data. Rather than lose
+            // every row after it, the row ends with the line the quote opened
+            // on, and reading carries on from the line after that.
+            let (quote_start, quote_line) = quote;
+            end = data[quote_start..]
+                .find('\n')
+                .map_or(data.len(), |i| quote_start + i);
+            next = (end + 1).min(data.len());
+            if data[..end].ends_with('\r') {
+                end -= 1;
+            }
+            line = quote_line + 1;
+            error = Some(CsvError::UnterminatedQuote);
+        }
+        fields.push(field);
+
+        let text = &data[start..end];
+        start = next;
+        if text.is_empty() {
+            continue;
+        }
+        rows.push(CsvRow {
+            line: row_line,
+            text: text.to_string(),
+            fields: match error {
+                Some(e) => Err(e),
+                None => Ok(fields),
+            },
+        });
+    }
+    rows
+}
+
+/// Formats a single CSV field, quoting it when it contains a comma,
+/// quote or line break.
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        // Quotes inside a quoted field are escaped by doubling them.
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Formats a complete CSV row, including the line ending.
+pub fn csv_row(fields: &[&str]) -> String {
+    let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+    format!("{}\n", fields.join(","))
+}
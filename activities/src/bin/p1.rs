@@ -19,18 +19,155 @@
 //   next level.
 
 // === L3 Attempt
-use std::collections::HashMap;
-use std::io;
+// NOTE The menu code is kept the way it was first written while learning, so
+// allow the lints that would rewrite it
+#![allow(clippy::comparison_to_empty, clippy::never_loop, clippy::question_mark)]
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use thiserror::Error;
 
-fn get_user_input() -> Option<String> {
-    let mut input = String::new();
-    // NOTE Handy trick of using while loop to keep reading until valid input
-    while io::stdin().read_line(&mut input).is_err() {
-        println!("Please enter your input again");
+/// Default location of the saved bills when no path is passed on the command line.
+const DEFAULT_DATA_FILE: &str = "p1_data.csv";
+
+/// Name of the ledger saved in the data file itself. Every other ledger is
+/// saved in a file next to it.
+const MAIN_LEDGER: &str = "main";
+
+/// Currency used when an amount is entered without a symbol or code.
+const DEFAULT_CURRENCY: Currency = Currency(*b"USD");
+
+/// Format used for due dates, both when typing them in and in the data file.
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Header line that starts the budgets section of the data file.
+const BUDGETS_HEADER: &str = "category,budget,currency";
+
+/// Header line that starts the household members section of the data file.
+/// Member names can't contain brackets, so it can't be mistaken for a member.
+const MEMBERS_HEADER: &str = "[members]";
+
+/// Header line that started the members section in older data files.
+const OLD_MEMBERS_HEADER: &str = "member";
+
+/// Header line that starts the list of the other ledgers in the main data file.
+const LEDGERS_HEADER: &str = "[ledgers]";
+
+/// Today's date in the local timezone.
+fn today() -> NaiveDate {
+    Local::now().naive_local().date()
+}
+
+/// The first day of the month `date` is in.
+fn month_start(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap_or(date)
+}
+
+/// Parses a month written as YYYY-MM into the first day of that month.
+fn parse_month(month: &str) -> Result<NaiveDate, chrono::ParseError> {
+    NaiveDate::parse_from_str(&format!("{}-01", month), DATE_FORMAT)
+}
+
+/// Prints rows as a table, with every column as wide as its widest value.
+/// Columns listed in `right_align` are right aligned, which suits amounts.
+fn print_table(
+    headers: &[&str],
+    right_align: &[usize],
+    rows: &[Vec<String>],
+    console: &mut dyn Console,
+) {
+    let mut widths: Vec<usize> = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| -> String {
+        let cells: Vec<String> = cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                if right_align.contains(&i) {
+                    format!("{:>width$}", cell, width = widths[i])
+                } else {
+                    format!("{:<width$}", cell, width = widths[i])
+                }
+            })
+            .collect();
+        cells.join("  ").trim_end().to_string()
+    };
+
+    console.print(&format_row(headers.to_vec()));
+    let separators: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    console.print(&separators.join("  "));
+    for row in rows {
+        console.print(&format_row(row.iter().map(|cell| cell.as_str()).collect()));
+    }
+}
+
+/// Adds whole months to a date. When the day doesn't exist in the target
+/// month (e.g. Jan 31 + 1 month) the last day of that month is used instead.
+fn add_months(date: NaiveDate, months: u32) -> NaiveDate {
+    let total = date.month0() + months;
+    let year = date.year() + (total / 12) as i32;
+    let month = total % 12 + 1;
+    // NOTE Every month has at least 28 days, so counting down always finds a valid date
+    let mut day = date.day();
+    loop {
+        if let Some(next) = NaiveDate::from_ymd_opt(year, month, day) {
+            return next;
+        }
+        day -= 1;
+    }
+}
+
+/// Where the menus read the user's input from and print their output to.
+/// The program uses the terminal, tests use a script of inputs instead so the
+/// menus can be checked without anyone typing.
+trait Console {
+    /// Reads a single line of input. An empty string means there is no more input.
+    fn read_line(&mut self) -> String;
+    /// Prints a single line of output.
+    fn print(&mut self, line: &str);
+}
+
+/// Reads from stdin and prints to stdout.
+struct Terminal;
+
+impl Console for Terminal {
+    fn read_line(&mut self) -> String {
+        let mut input = String::new();
+        // NOTE Handy trick of using while loop to keep reading until valid input
+        while io::stdin().read_line(&mut input).is_err() {
+            println!("Please enter your input again");
+        }
+        input
+    }
+
+    fn print(&mut self, line: &str) {
+        println!("{}", line);
     }
+}
+
+fn get_user_input(console: &mut dyn Console) -> Option<String> {
+    get_user_input_keep_case(console).map(|input| input.to_lowercase())
+}
+
+/// Same as get_user_input(), but without lowercasing, for things like
+/// bill names that should be shown the way they were typed.
+fn get_user_input_keep_case(console: &mut dyn Console) -> Option<String> {
+    let input = console.read_line();
     // NOTE Give the user a chance to go back/exit the menu. To do this,
     // need to return Option<String> so we can use Some/None
-    let input = input.trim().to_lowercase();
+    let input = input.trim().to_string();
     // Q: Need to borrow input? (&input)
     if &input == "" {
         None
@@ -48,7 +185,98 @@ struct Bills {
     // inner: Vec<Bill>,
     // NOTE For HashMap, you can have the key be String and the value
     // be a full Bill struct. I originally used HashMap<String, f64>
-    inner: HashMap<String, Bill>,
+    // UPDATE Keyed on the bill's id now, so renaming a bill doesn't move it
+    inner: HashMap<u64, Bill>,
+    /// Id given to the next bill that is added.
+    next_id: u64,
+    /// Monthly spending limit for each category that has one.
+    budgets: BTreeMap<Category, Money>,
+    /// Names of the people in the household that bills can be split between.
+    members: BTreeSet<String>,
+    /// Names of the other ledgers. Only the main ledger keeps this list.
+    ledgers: BTreeSet<String>,
+    /// Changes made this session, most recent last. Not saved to the data file.
+    undo_stack: Vec<Change>,
+    /// Changes that were undone and can be redone, most recently undone last.
+    redo_stack: Vec<Change>,
+}
+
+/// A single change to the bills. Every change stores the state from before
+/// and after it was made, so it can be undone and redone any number of times.
+#[derive(Debug, Clone)]
+enum Change {
+    /// Sets the bill with id `id`, None meaning there is no bill.
+    Bill {
+        id: u64,
+        // NOTE Boxed since a Bill is much bigger than the other changes
+        before: Option<Box<Bill>>,
+        after: Option<Box<Bill>>,
+    },
+    /// Sets the budget for `category`, None meaning there is no budget.
+    Budget {
+        category: Category,
+        before: Option<Money>,
+        after: Option<Money>,
+    },
+    /// Adds (or when `added` is false, removes) a household member.
+    Member { name: String, added: bool },
+}
+
+impl Change {
+    fn apply(&self, bills: &mut Bills) {
+        match self {
+            Change::Bill { id, after, .. } => bills.replace_bill(*id, after.as_deref().cloned()),
+            Change::Budget {
+                category, after, ..
+            } => bills.replace_budget(category, *after),
+            Change::Member { name, added } => bills.replace_member(name, *added),
+        }
+    }
+
+    fn revert(&self, bills: &mut Bills) {
+        match self {
+            Change::Bill { id, before, .. } => bills.replace_bill(*id, before.as_deref().cloned()),
+            Change::Budget {
+                category, before, ..
+            } => bills.replace_budget(category, *before),
+            Change::Member { name, added } => bills.replace_member(name, !*added),
+        }
+    }
+
+    /// A short description of the change, e.g. "edit rent".
+    fn describe(&self) -> String {
+        match self {
+            Change::Bill {
+                before: None,
+                after: Some(after),
+                ..
+            } => format!("add {}", after.name),
+            Change::Bill {
+                before: Some(before),
+                after: None,
+                ..
+            } => format!("remove {}", before.name),
+            Change::Bill {
+                before: Some(before),
+                after: Some(after),
+                ..
+            } if after.name != before.name => format!("rename {} to {}", before.name, after.name),
+            Change::Bill {
+                before: Some(before),
+                after: Some(after),
+                ..
+            } if after.payments.len() > before.payments.len() => {
+                format!("payment on {}", after.name)
+            }
+            Change::Bill {
+                after: Some(bill), ..
+            } => format!("edit {}", bill.name),
+            Change::Bill { id, .. } => format!("bill #{}", id),
+            Change::Budget { category, .. } => format!("budget for {}", category),
+            Change::Member { name, added: true } => format!("add member {}", name),
+            Change::Member { name, added: false } => format!("remove member {}", name),
+        }
+    }
 }
 
 impl Bills {
@@ -60,16 +288,54 @@ impl Bills {
         // Self { inner: vec![] }
         Self {
             inner: HashMap::new(),
+            next_id: 1,
+            budgets: BTreeMap::new(),
+            members: BTreeSet::new(),
+            ledgers: BTreeSet::new(),
+            undo_stack: vec![],
+            redo_stack: vec![],
         }
     }
 
-    fn add_bill_menu(&mut self) {
-        let new_bill = Bill::new();
-        match new_bill {
-            Some(inner_bill) => self.add_bill(inner_bill),
-            None => (),
-        }
+    fn add_bill_menu(&mut self, console: &mut dyn Console) {
+        let new_bill = Bill::new(console);
+        let inner_bill = match new_bill {
+            Some(inner_bill) => inner_bill,
+            None => return,
+        };
         // self.add_bill(new_bill);
+
+        // NOTE Names are compared ignoring case, so "Rent" and "rent" are the same bill
+        let existing = match self.find(&inner_bill.name) {
+            Some(existing) => existing.clone(),
+            None => {
+                self.add_bill(inner_bill);
+                return;
+            }
+        };
+        console.print(&format!("A bill named {:?} already exists:", existing.name));
+        console.print("'merge' - Update it with the new details, keeping its payments");
+        console.print("'replace' - Replace it with the new bill, dropping its payments");
+        console.print("'cancel' - Keep the existing bill as it is");
+        loop {
+            match get_user_input(console).as_deref() {
+                Some("merge") => {
+                    console.print(&format!("Merged into {:?}", existing.name));
+                    self.set_bill(existing.id, Some(existing.merged(inner_bill)));
+                    return;
+                }
+                Some("replace") => {
+                    console.print(&format!("Replaced {:?}", existing.name));
+                    self.set_bill(existing.id, Some(existing.replaced(inner_bill)));
+                    return;
+                }
+                Some("cancel") | None => {
+                    console.print(&format!("Kept {:?}", existing.name));
+                    return;
+                }
+                Some(_) => console.print("Please enter merge, replace or cancel"),
+            }
+        }
     }
     // Q: Do I return Result type, Self, or nothing at all?
     // I can return Self for new() but doesn't make sense for other methods.
@@ -77,255 +343,3928 @@ impl Bills {
     // UPDATE Trying to pass the Bill as arg.
     // Q: How to make the bills vector mutable?
     // A: You gotta have a &mut self reference otherwise can't figure out how
-    fn add_bill(&mut self, bill: Bill) {
+    // UPDATE Gives the bill a new id (whatever id it had is ignored) and returns it.
+    // Doesn't check for duplicate names, add_bill_menu() does that first.
+    fn add_bill(&mut self, mut bill: Bill) -> u64 {
         // NOTE With HashMap need to use clone(), otherwise bill.name gets moved into
         // the HashMap. HashMaps require an owned String for the Key, but if we move
         // the original bill.name (no clone) into the HashMap, then we no longer have
         // access to bill.name because the Bill will no longer have ownership of the name
         // property, and therefore won't properly compile (partial move error)
-        self.inner.insert(bill.name.clone(), bill);
+        // UPDATE The insert happens in replace_bill() now so the change can be undone
+        // UPDATE The key is the id now, which is Copy so no clone needed
+        bill.id = self.next_id;
+        self.next_id += 1;
+        let id = bill.id;
+        self.set_bill(id, Some(bill));
+        id
+    }
+
+    /// Sets (or with None, removes) the bill with id `id`, and adds the
+    /// change to the undo history.
+    fn set_bill(&mut self, id: u64, bill: Option<Bill>) {
+        let change = Change::Bill {
+            id,
+            before: self.inner.get(&id).cloned().map(Box::new),
+            after: bill.map(Box::new),
+        };
+        self.record(change);
+    }
+
+    /// Finds a bill by name, ignoring case.
+    fn find(&self, name: &str) -> Option<&Bill> {
+        let name = name.to_lowercase();
+        self.inner
+            .values()
+            .find(|bill| bill.name.to_lowercase() == name)
+    }
+
+    /// Sets (or with None, removes) the budget for `category`, and adds the
+    /// change to the undo history.
+    fn set_budget(&mut self, category: Category, budget: Option<Money>) {
+        let change = Change::Budget {
+            before: self.budgets.get(&category).copied(),
+            category,
+            after: budget,
+        };
+        self.record(change);
+    }
+
+    fn record(&mut self, change: Change) {
+        change.apply(self);
+        self.undo_stack.push(change);
+        // NOTE A new change starts a new "branch" of history, so anything that was
+        // undone before it can't be redone anymore
+        self.redo_stack.clear();
+    }
+
+    /// Undoes the most recent change, returning its description.
+    fn undo(&mut self) -> Option<String> {
+        let change = self.undo_stack.pop()?;
+        change.revert(self);
+        let description = change.describe();
+        self.redo_stack.push(change);
+        Some(description)
+    }
+
+    /// Redoes the most recently undone change, returning its description.
+    fn redo(&mut self) -> Option<String> {
+        let change = self.redo_stack.pop()?;
+        change.apply(self);
+        let description = change.describe();
+        self.undo_stack.push(change);
+        Some(description)
+    }
+
+    fn undo_menu(&mut self, console: &mut dyn Console) {
+        match self.undo() {
+            Some(description) => console.print(&format!("Undid {}", description)),
+            None => console.print("Nothing to undo"),
+        }
     }
 
-    fn view_bills_menu(&self) {
+    fn redo_menu(&mut self, console: &mut dyn Console) {
+        match self.redo() {
+            Some(description) => console.print(&format!("Redid {}", description)),
+            None => console.print("Nothing to redo"),
+        }
+    }
+
+    /// Puts a bill in the map without recording any history.
+    fn replace_bill(&mut self, id: u64, bill: Option<Bill>) {
+        match bill {
+            Some(bill) => self.inner.insert(id, bill),
+            None => self.inner.remove(&id),
+        };
+    }
+
+    /// Puts a budget in the map without recording any history.
+    fn replace_budget(&mut self, category: &Category, budget: Option<Money>) {
+        match budget {
+            Some(budget) => self.budgets.insert(category.clone(), budget),
+            None => self.budgets.remove(category),
+        };
+    }
+
+    /// Adds or removes a member without recording any history.
+    fn replace_member(&mut self, name: &str, present: bool) {
+        if present {
+            self.members.insert(name.to_string());
+        } else {
+            self.members.remove(name);
+        }
+    }
+
+    fn view_bills_menu(&self, console: &mut dyn Console) {
         // === Bills as Vec
         // let bills = self.view_bills();
         // println!("bills = {:?}", bills);
         // === Bills as HashMap
-        self.view_bills();
+        self.view_bills(console);
     }
 
-    fn view_bills(&self) {
+    fn view_bills(&self, console: &mut dyn Console) {
         // NOTE When iterating over HashMaps, the (k, v) are BORROWED (&String, &Bill)
-        // If Bills was a Vec, then we'd have to use clone() to stored owned values
-        for (bill, amount) in self.inner.iter() {
-            println!("bill = {:?}, amount = {:?}", bill, amount);
+        // If Bills was a Vec, then we'd have to use clone() to stored owned values.
+        // to_sorted_vec() borrows them as well, it just puts them in order first.
+        let rows: Vec<Vec<String>> = self
+            .to_sorted_vec()
+            .into_iter()
+            .map(|bill| {
+                vec![
+                    bill.name.clone(),
+                    bill.category.to_string(),
+                    bill.amount.to_string(),
+                    bill.remaining().to_string(),
+                    bill.due.format(DATE_FORMAT).to_string(),
+                    bill.recurrence.as_str().to_string(),
+                    bill.split
+                        .as_ref()
+                        .map(|split| split.to_string())
+                        .unwrap_or_default(),
+                ]
+            })
+            .collect();
+        if rows.is_empty() {
+            console.print("No bills yet");
+            return;
+        }
+        print_table(
+            &[
+                "name",
+                "category",
+                "amount",
+                "remaining",
+                "due",
+                "repeats",
+                "split",
+            ],
+            &[2, 3],
+            &rows,
+            console,
+        );
+        console.print("");
+        for total in self.totals() {
+            console.print(&format!("total = {}", total));
+        }
+    }
+
+    /// Adds up the bills, one total per currency since amounts in different
+    /// currencies can't be summed together.
+    fn totals(&self) -> Vec<Money> {
+        // NOTE BTreeMap keeps the currencies sorted, so the output order is stable
+        let mut totals: BTreeMap<Currency, i64> = BTreeMap::new();
+        for bill in self.inner.values() {
+            *totals.entry(bill.amount.currency).or_insert(0) += bill.amount.cents;
+        }
+        totals
+            .into_iter()
+            .map(|(currency, cents)| Money { cents, currency })
+            .collect()
+    }
+
+    /// Shows overdue bills and bills due within a number of days from today.
+    fn due_bills_menu(&self, console: &mut dyn Console) {
+        let days = loop {
+            console.print("Show bills due within how many days?");
+            let days = match get_user_input(console) {
+                Some(days) => days,
+                None => return,
+            };
+            match days.parse::<i64>() {
+                Ok(days) if days >= 0 => break days,
+                _ => console.print("Please enter a whole number of days"),
+            }
+        };
+        self.due_bills(today(), days, console);
+    }
+
+    fn due_bills(&self, today: NaiveDate, days: i64, console: &mut dyn Console) {
+        let (overdue, upcoming) = self.due_within(today, days);
+
+        console.print("\n** Overdue **");
+        if overdue.is_empty() {
+            console.print("Nothing overdue");
+        }
+        for bill in overdue {
+            let late = (today - bill.due).num_days();
+            console.print(&format!(
+                "{} - {} - due {} ({} day(s) late)",
+                bill.name,
+                bill.remaining(),
+                bill.due,
+                late
+            ));
+        }
+
+        console.print(&format!("\n** Due in the next {} day(s) **", days));
+        if upcoming.is_empty() {
+            console.print("Nothing due");
+        }
+        for bill in upcoming {
+            let left = (bill.due - today).num_days();
+            console.print(&format!(
+                "{} - {} - due {} (in {} day(s))",
+                bill.name,
+                bill.remaining(),
+                bill.due,
+                left
+            ));
+        }
+    }
+
+    /// Returns the unpaid bills that are overdue, and the ones due within
+    /// `days` days of `today`, both sorted by due date.
+    fn due_within(&self, today: NaiveDate, days: i64) -> (Vec<&Bill>, Vec<&Bill>) {
+        let window_end = today + Duration::days(days);
+        let mut overdue = vec![];
+        let mut upcoming = vec![];
+        for bill in self.inner.values().filter(|bill| !bill.paid) {
+            if bill.due < today {
+                overdue.push(bill);
+            } else if bill.due <= window_end {
+                upcoming.push(bill);
+            }
+        }
+        overdue.sort_by_key(|bill| bill.due);
+        upcoming.sort_by_key(|bill| bill.due);
+        (overdue, upcoming)
+    }
+
+    fn pay_bill_menu(&mut self, console: &mut dyn Console) {
+        console.print("Enter name of bill to PAY:");
+        let name = match get_user_input(console) {
+            Some(name) => name,
+            None => return,
+        };
+        // NOTE Pay a copy of the bill, then swap it in with set_bill() so the
+        // payment can be undone
+        let mut bill = match self.find(&name) {
+            Some(bill) => bill.clone(),
+            None => {
+                console.print("No matching bill to pay");
+                return;
+            }
+        };
+        if bill.paid {
+            console.print(&format!("{:?} is already paid", bill.name));
+            return;
+        }
+        // NOTE Only payments on split bills need a payer, to know who is owed what
+        let by = if bill.split.is_some() {
+            match self.get_member(console) {
+                Some(member) => Some(member),
+                None => return,
+            }
+        } else {
+            None
+        };
+
+        let date = match Bill::get_payment_date(console) {
+            Some(date) => date,
+            None => return,
+        };
+        loop {
+            console.print(&format!(
+                "Enter amount paid ('full' pays the {} remaining):",
+                bill.remaining()
+            ));
+            let amount = match get_user_input(console) {
+                Some(amount) => amount,
+                None => return,
+            };
+            let amount = if amount == "full" {
+                bill.remaining()
+            } else {
+                // NOTE Amounts typed without a currency are in the bill's currency
+                match Money::parse_in(&amount, bill.amount.currency) {
+                    Ok(amount) => amount,
+                    Err(e) => {
+                        console.print(&format!("Please enter a valid amount: {}", e));
+                        continue;
+                    }
+                }
+            };
+            match bill.pay(date, amount, by.clone()) {
+                Ok(()) => break,
+                Err(e) => console.print(&format!("{}", e)),
+            }
+        }
+
+        if bill.paid {
+            console.print(&format!("{:?} is now fully paid", bill.name));
+        } else if bill.payments.last().map(|p| p.due) != Some(bill.due) {
+            console.print(&format!(
+                "{:?} is paid for this period, next due {}",
+                bill.name, bill.due
+            ));
+        } else {
+            console.print(&format!(
+                "{} still owed on {:?}",
+                bill.remaining(),
+                bill.name
+            ));
+        }
+        self.set_bill(bill.id, Some(bill));
+    }
+
+    fn history_menu(&self, console: &mut dyn Console) {
+        console.print("\n** Payments by bill **");
+        for bill in self.to_sorted_vec() {
+            console.print(&format!(
+                "{} ({} remaining, due {})",
+                bill.name,
+                bill.remaining(),
+                bill.due
+            ));
+            if bill.payments.is_empty() {
+                console.print("  no payments");
+            }
+            for payment in bill.payments.iter() {
+                console.print(&format!(
+                    "  {} - {} (for {})",
+                    payment.date, payment.amount, payment.due
+                ));
+            }
+        }
+
+        console.print("\n** Payments by month **");
+        for (month, payments) in self.payments_by_month() {
+            console.print(&month);
+            let mut totals: BTreeMap<Currency, i64> = BTreeMap::new();
+            for (name, payment) in payments {
+                console.print(&format!(
+                    "  {} - {} - {}",
+                    payment.date, name, payment.amount
+                ));
+                *totals.entry(payment.amount.currency).or_insert(0) += payment.amount.cents;
+            }
+            for (currency, cents) in totals {
+                console.print(&format!("  total = {}", Money { cents, currency }));
+            }
+        }
+    }
+
+    /// Groups every payment by the month it was made in ("YYYY-MM"), oldest
+    /// month first. Each payment is paired with the name of its bill.
+    fn payments_by_month(&self) -> BTreeMap<String, Vec<(&str, &Payment)>> {
+        let mut months: BTreeMap<String, Vec<(&str, &Payment)>> = BTreeMap::new();
+        for bill in self.inner.values() {
+            for payment in bill.payments.iter() {
+                months
+                    .entry(payment.date.format("%Y-%m").to_string())
+                    .or_default()
+                    .push((&bill.name, payment));
+            }
+        }
+        for payments in months.values_mut() {
+            payments.sort_by_key(|(name, payment)| (payment.date, *name));
+        }
+        months
+    }
+
+    fn set_budget_menu(&mut self, console: &mut dyn Console) {
+        let category = match Bill::get_bill_category(console) {
+            Some(category) => category,
+            None => return,
+        };
+        loop {
+            console.print(&format!(
+                "Enter monthly budget for {} ('none' removes it):",
+                category
+            ));
+            let budget = match get_user_input(console) {
+                Some(budget) => budget,
+                None => return,
+            };
+            if budget == "none" {
+                console.print(&format!("Removed budget for {}", category));
+                self.set_budget(category, None);
+                return;
+            }
+            match Money::parse(&budget) {
+                Ok(budget) => {
+                    console.print(&format!("Budget for {} set to {}", category, budget));
+                    self.set_budget(category, Some(budget));
+                    return;
+                }
+                Err(e) => console.print(&format!("Please enter a valid amount: {}", e)),
+            }
+        }
+    }
+
+    fn summary_menu(&self, console: &mut dyn Console) {
+        let month = loop {
+            console.print("Enter month to summarize (YYYY-MM or 'this'):");
+            let month = match get_user_input(console) {
+                Some(month) => month,
+                None => return,
+            };
+            if month == "this" {
+                break month_start(today());
+            }
+            match parse_month(&month) {
+                Ok(month) => break month,
+                Err(_) => console.print(&format!(
+                    "Please enter a valid month, e.g. {}",
+                    today().format("%Y-%m")
+                )),
+            }
+        };
+        self.summary(month, console);
+    }
+
+    /// Prints how much each category costs in the month starting on
+    /// `month`, compared against its budget.
+    fn summary(&self, month: NaiveDate, console: &mut dyn Console) {
+        console.print(&format!("\n** Summary for {} **", month.format("%Y-%m")));
+        let rows: Vec<Vec<String>> = self
+            .spending_by_category(month)
+            .into_iter()
+            .map(|(category, spent)| {
+                let (budget, status) = match self.budgets.get(&category) {
+                    Some(budget) if budget.currency == spent.currency => {
+                        let status = if spent.cents > budget.cents {
+                            let over = Money {
+                                cents: spent.cents - budget.cents,
+                                ..spent
+                            };
+                            format!("OVER by {}", over)
+                        } else {
+                            "ok".to_string()
+                        };
+                        (budget.to_string(), status)
+                    }
+                    Some(budget) => (
+                        budget.to_string(),
+                        format!("budget is in {}", budget.currency),
+                    ),
+                    None => ("-".to_string(), "no budget".to_string()),
+                };
+                vec![category.to_string(), spent.to_string(), budget, status]
+            })
+            .collect();
+        if rows.is_empty() {
+            console.print("No bills or budgets yet");
+            return;
+        }
+        print_table(
+            &["category", "spending", "budget", "status"],
+            &[1, 2],
+            &rows,
+            console,
+        );
+    }
+
+    /// Adds up what the bills in each category cost in the month starting
+    /// on `month`, sorted by category name. Categories with a budget but no
+    /// bills are included with a zero amount.
+    fn spending_by_category(&self, month: NaiveDate) -> Vec<(Category, Money)> {
+        let mut spending: BTreeMap<(Category, Currency), i64> = BTreeMap::new();
+        for (category, budget) in self.budgets.iter() {
+            spending.insert((category.clone(), budget.currency), 0);
         }
+        for bill in self.inner.values() {
+            let times = bill.times_due_in_month(month);
+            *spending
+                .entry((bill.category.clone(), bill.amount.currency))
+                .or_insert(0) += bill.amount.cents * times;
+        }
+        let mut spending: Vec<(Category, Money)> = spending
+            .into_iter()
+            .map(|((category, currency), cents)| (category, Money { cents, currency }))
+            .collect();
+        // NOTE Sort by name so custom categories are mixed in alphabetically
+        spending.sort_by_key(|(category, spent)| (category.to_string(), spent.currency));
+        spending
+    }
+
+    /// Converts the bills into a vector sorted by name.
+    /// This is used when saving so the file contents are stable between saves.
+    fn to_sorted_vec(&self) -> Vec<&Bill> {
+        let mut bills: Vec<&Bill> = self.inner.values().collect();
+        bills.sort_by_key(|bill| (bill.name.to_lowercase(), bill.id));
+        bills
     }
 
-    fn remove_bill_menu(&mut self) {
-        self.remove_bill();
+    fn remove_bill_menu(&mut self, console: &mut dyn Console) {
+        self.remove_bill(console);
     }
 
-    fn remove_bill(&mut self) {
-        println!("Enter name of bill to REMOVE:");
-        let bill_to_remove = match get_user_input() {
+    fn remove_bill(&mut self, console: &mut dyn Console) {
+        console.print("Enter name of bill to REMOVE:");
+        let bill_to_remove = match get_user_input(console) {
             Some(bill_to_remove) => bill_to_remove,
             None => return,
         };
-        if self.inner.contains_key(&bill_to_remove) {
-            self.inner.remove(&bill_to_remove);
-            println!("Removed bill: {:?}", &bill_to_remove);
+        if self.remove(&bill_to_remove).is_some() {
+            console.print(&format!("Removed bill: {:?}", &bill_to_remove));
         } else {
-            println!("Bill not found. Please double-check bill name and try again.");
+            console.print("Bill not found. Please double-check bill name and try again.");
             // Q: Do I need to re-run remove_bill_menu()?
             // A: Doesn't seem so because this is called within a loop inside main()
         }
         // println!("Removed bill = {:?}", self.inner[&bill_to_remove]);
     }
 
-    fn edit_bill_menu(&mut self) {
-        self.edit_bill();
+    /// Removes a bill by name (ignoring case), returning it if it existed.
+    fn remove(&mut self, name: &str) -> Option<Bill> {
+        let bill = self.find(name).cloned()?;
+        self.set_bill(bill.id, None);
+        Some(bill)
+    }
+
+    fn edit_bill_menu(&mut self, console: &mut dyn Console) {
+        self.edit_bill(console);
     }
 
-    fn edit_bill(&mut self) {
-        println!("Enter name of bill to EDIT:");
-        let bill_name_to_edit = match get_user_input() {
+    fn edit_bill(&mut self, console: &mut dyn Console) {
+        console.print("Enter name of bill to EDIT:");
+        let bill_name_to_edit = match get_user_input(console) {
             Some(bill_name_to_edit) => bill_name_to_edit,
             None => return,
         };
 
-
-        // Q: Do I really need to first do an if self.inner.contains_key() check 
-        // if I also use inner.get_mut(), which returns an Option<&mut Bill>, 
+        // Q: Do I really need to first do an if self.inner.contains_key() check
+        // if I also use inner.get_mut(), which returns an Option<&mut Bill>,
         // which means I need to handle the None variant anyway?
         // NOTE Both together works. Let's see if I can remove the contains_key() check...
         // A: NOPE! I can remove the if self.inner.contains_key() check and just use .get_mut()
-        let bill_to_edit: Option<&mut Bill> = self.inner.get_mut(&bill_name_to_edit);
-        // NOTE Could just directly: match self.inner.get_mut(&bill_name_to_edit) {...}
-        match bill_to_edit {
-            Some(bill) => {
+        // UPDATE Edit a clone instead so set_bill() can record the change for undo
+        let bill_to_edit: Option<Bill> = self.find(&bill_name_to_edit).cloned();
+        let mut bill = match bill_to_edit {
+            Some(bill) => bill,
+            None => {
+                console.print("No matching bill to edit");
+                return;
+            }
+        };
+
+        loop {
+            console.print("What do you want to change? (name, amount, due, repeats, category)");
+            let field = match get_user_input(console) {
+                Some(field) => field,
+                None => return,
+            };
             // Q: What's a standard way of editing a k:v pair in HashMaps if I wanted to update
             // BOTH the name and amount?
             // NOTE If they change the name then we need to update the KEY value as well
+            // UPDATE Not anymore! The key is the bill's id, which never changes
             // Q: Do I even need to do this loop? Tutorial just updates bill.amount basically
-            // A: Yes! Still need to do the whole String to f64 conversion...
-            loop {
-                println!("Enter bill amount:");
-                let amount = match get_user_input() {
-                    Some(amount) => amount,
-                    None => return,
-                };
-                // NOTE Trick is to return Result<f64, _> instead of f64
-                let parsed_amount: Result<f64, _> =  amount.parse();
-                match parsed_amount {
-                    // IMPORTANT MUST 'return' from inside loop, otherwise infinite!
-                    Ok(inner_amount) => {
-                        bill.amount = inner_amount;
-                        return
-                    },
-                    Err(_) => println!("Please enter a valid number"),
+            // A: Yes! Still need to do the whole String to Money conversion, which
+            // get_bill_amount() already loops over
+            let edited = match field.as_str() {
+                "name" => self
+                    .get_new_name(bill.id, console)
+                    .map(|name| bill.name = name),
+                "amount" => Bill::get_bill_amount(console).map(|amount| bill.amount = amount),
+                "due" => Bill::get_bill_due_date(console).map(|due| bill.due = due),
+                "repeats" => Bill::get_bill_recurrence(console)
+                    .map(|recurrence| bill.recurrence = recurrence),
+                "category" => {
+                    Bill::get_bill_category(console).map(|category| bill.category = category)
                 }
+                _ => {
+                    console.print("Please enter name, amount, due, repeats or category");
+                    continue;
+                }
+            };
+            if edited.is_some() {
+                self.set_bill(bill.id, Some(bill));
             }
-            }
-            None => println!("No matching bill to edit"),
+            return;
         }
     }
-}
 
-#[derive(Debug)]
-enum MenuOption {
-    Add,
-    View,
-    Remove,
-    Edit,
+    /// Asks for a new name for the bill with id `id`, making sure no other
+    /// bill already has it.
+    fn get_new_name(&self, id: u64, console: &mut dyn Console) -> Option<String> {
+        loop {
+            let name = Bill::get_bill_name(console)?;
+            match self.find(&name) {
+                Some(other) if other.id != id => {
+                    console.print(&format!("There is already a bill named {:?}", other.name))
+                }
+                _ => return Some(name),
+            }
+        }
+    }
 }
 
-impl MenuOption {
-    fn show_menu() {
-        println!("\n** Manage Bills **");
-        println!("'add' - Add bill");
-        println!("'view' - View bills");
-        println!("'edit' - Edit bill");
-        println!("'remove' - Remove bill");
-        println!("'quit' - Quit");
-        println!("-------------------\n");
-    }
-
-    fn get_user_option() -> Option<MenuOption> {
-        let input = match get_user_input() {
-            Some(input) => input,
-            // FIXME Error with just returning ()...
-            // Q: How to use None => return if the function expects to 
-            // return a String? Getting an error...
-            // A: Looks like when the fn returns an Option, you can
-            // use None => return None, to return the correct type.
-            // NOTE return; is same as return ();
-            // None => return,  // Error
-            None => return None,
-        };
+impl Bills {
+    /// Finds a household member by name, ignoring case.
+    fn find_member(&self, name: &str) -> Option<&String> {
+        self.members
+            .iter()
+            .find(|member| member.eq_ignore_ascii_case(name.trim()))
+    }
 
-        match input.as_str() {
-            "add" => Some(MenuOption::Add),
-            "view" => Some(MenuOption::View),
-            "edit" => Some(MenuOption::Edit),
-            "remove" => Some(MenuOption::Remove),
-            _ => None,
+    /// Adds a household member, and adds the change to the undo history.
+    fn add_member(&mut self, name: &str) -> Result<(), HouseholdError> {
+        let name = name.trim();
+        // NOTE These characters separate the splits and payments in the data
+        // file, and brackets mark the start of the members section
+        if name.is_empty() || name.contains(|ch| ",;:|=[]".contains(ch)) {
+            return Err(HouseholdError::InvalidMemberName(name.to_string()));
         }
+        if let Some(member) = self.find_member(name) {
+            return Err(HouseholdError::MemberExists(member.clone()));
+        }
+        self.record(Change::Member {
+            name: name.to_string(),
+            added: true,
+        });
+        Ok(())
     }
 
-    // Could also consider returning a Result<(), String> based on whether
-    // the CRUD commands are successful. This would give me the added bonus
-    // of being able to use the ? operator on the CRUD methods as well.
-    fn process_option(option: MenuOption, bills: &mut Bills) {
-        // Gather the name of the bill from user
-        // let name = get_user_input();
+    /// Removes a household member, as long as no bill is split with them or
+    /// has a payment made by them. Returns the member's name.
+    fn remove_member(&mut self, name: &str) -> Result<String, HouseholdError> {
+        let member = self
+            .find_member(name)
+            .cloned()
+            .ok_or_else(|| HouseholdError::UnknownMember(name.to_string()))?;
+        for bill in self.to_sorted_vec() {
+            let in_split =
+                matches!(&bill.split, Some(split) if split.members().contains(&member.as_str()));
+            let paid = bill
+                .payments
+                .iter()
+                .any(|payment| payment.by.as_ref() == Some(&member));
+            if in_split || paid {
+                return Err(HouseholdError::MemberInUse {
+                    member,
+                    bill: bill.name.clone(),
+                });
+            }
+        }
+        self.record(Change::Member {
+            name: member.clone(),
+            added: false,
+        });
+        Ok(member)
+    }
 
-        // Q: Where should I create the new Bills?
-        // A: For now I've initialized inside main so I can pass Bills
-        // and MenuOption to this function.
-        match option {
-            MenuOption::Add => Bills::add_bill_menu(bills),
-            MenuOption::View => Bills::view_bills_menu(bills),
-            MenuOption::Edit => Bills::edit_bill_menu(bills),
-            MenuOption::Remove => Bills::remove_bill_menu(bills),
+    fn members_menu(&mut self, console: &mut dyn Console) {
+        console.print("\n** Household members **");
+        if self.members.is_empty() {
+            console.print("No members yet");
+        }
+        for member in self.members.iter() {
+            console.print(&format!("  {}", member));
+        }
+        console.print("Enter 'add <name>' or 'remove <name>':");
+        // NOTE Keep the case so names are shown the way they were typed
+        let input = match get_user_input_keep_case(console) {
+            Some(input) => input,
+            None => return,
+        };
+        let (action, name) = input.split_once(' ').unwrap_or((&input, ""));
+        match action.to_lowercase().as_str() {
+            "add" => match self.add_member(name) {
+                Ok(()) => console.print(&format!("Added member {:?}", name.trim())),
+                Err(e) => console.print(&e.to_string()),
+            },
+            "remove" => match self.remove_member(name) {
+                Ok(member) => console.print(&format!("Removed member {:?}", member)),
+                Err(e) => console.print(&e.to_string()),
+            },
+            _ => console.print("Please enter add or remove followed by a name"),
         }
     }
-}
 
-#[derive(Debug)]
-struct Bill {
-    name: String,
-    amount: f64,
-}
+    /// Asks which member made a payment.
+    fn get_member(&self, console: &mut dyn Console) -> Option<String> {
+        let members: Vec<&str> = self.members.iter().map(|member| member.as_str()).collect();
+        loop {
+            console.print(&format!("Who paid? ({})", members.join(", ")));
+            let name = get_user_input(console)?;
+            match self.find_member(&name) {
+                Some(member) => return Some(member.clone()),
+                None => console.print(&format!("{:?} is not a household member", name)),
+            }
+        }
+    }
 
-impl Bill {
-    fn new() -> Option<Self> {
-        println!("Enter bill name:");
-        let name = match get_user_input() {
+    fn split_bill_menu(&mut self, console: &mut dyn Console) {
+        if self.members.is_empty() {
+            console.print("Add household members with 'members' first");
+            return;
+        }
+        console.print("Enter name of bill to SPLIT:");
+        let name = match get_user_input(console) {
             Some(name) => name,
-            None => return None, // Error: return type is not () unit type (it's 'Bill')
-            // Q: Do I need to make new() return an Option or Result? Again, I'm trying
-            // to give user a chance to cancel/go back in the menu by making get_user_input()
-            // return an Option type.
-            // A: Yes! Make this return Option<Self> and then that enables me to use the
+            None => return,
+        };
+        let mut bill = match self.find(&name) {
+            Some(bill) => bill.clone(),
+            None => {
+                console.print("No matching bill to split");
+                return;
+            }
+        };
+
+        loop {
+            console.print(&format!(
+                "How is {:?} split? (equal, percent, fixed, or 'none' to stop splitting it)",
+                bill.name
+            ));
+            let kind = match get_user_input(console) {
+                Some(kind) => kind,
+                None => return,
+            };
+            let prompt = match kind.as_str() {
+                "none" => {
+                    console.print(&format!("{:?} is no longer split", bill.name));
+                    bill.split = None;
+                    self.set_bill(bill.id, Some(bill));
+                    return;
+                }
+                "equal" => "Enter the members sharing it, e.g. alice, bob:",
+                "percent" => "Enter each member's percentage, e.g. alice 60, bob 40:",
+                "fixed" => "Enter each member's amount, e.g. alice 700, bob 500:",
+                _ => {
+                    console.print("Please enter equal, percent, fixed or none");
+                    continue;
+                }
+            };
+            console.print(prompt);
+            let shares = match get_user_input(console) {
+                Some(shares) => shares,
+                None => return,
+            };
+            let split = Split::from_input(&kind, &shares, bill.amount.currency)
+                .and_then(|split| self.check_split(split, bill.amount));
+            match split {
+                Ok(split) => {
+                    console.print(&format!("{:?} is now split {}", bill.name, split));
+                    bill.split = Some(split);
+                    self.set_bill(bill.id, Some(bill));
+                    return;
+                }
+                Err(e) => console.print(&e.to_string()),
+            }
+        }
+    }
+
+    /// Checks a split works for a bill of `amount`, and that everyone in it
+    /// is a member. Returns the split with the names as the members were added.
+    fn check_split(&self, mut split: Split, amount: Money) -> Result<Split, HouseholdError> {
+        for name in split.members_mut() {
+            match self.find_member(name) {
+                Some(member) => *name = member.clone(),
+                None => return Err(HouseholdError::UnknownMember(name.clone())),
+            }
+        }
+        split.check(amount)?;
+        Ok(split)
+    }
+
+    /// How much each member is owed (positive) or owes (negative) in each
+    /// currency, from the payments made on split bills. Payments made before
+    /// the bill was split have no payer, so they aren't counted.
+    fn balances(&self) -> BTreeMap<Currency, BTreeMap<&str, i64>> {
+        let mut balances: BTreeMap<Currency, BTreeMap<&str, i64>> = BTreeMap::new();
+        for bill in self.inner.values() {
+            let split = match &bill.split {
+                Some(split) => split,
+                None => continue,
+            };
+            for payment in bill.payments.iter() {
+                let by = match &payment.by {
+                    Some(by) => by,
+                    None => continue,
+                };
+                let balance = balances.entry(payment.amount.currency).or_default();
+                *balance.entry(by).or_insert(0) += payment.amount.cents;
+                for (member, share) in split.shares(payment.amount) {
+                    *balance.entry(member).or_insert(0) -= share.cents;
+                }
+            }
+        }
+        balances
+    }
+
+    /// The transfers that settle everyone's balances. Whoever owes the most
+    /// pays whoever is owed the most until everyone is even, which takes at
+    /// most one transfer less than the number of members with a balance.
+    fn settlement(&self) -> Vec<Transfer> {
+        let mut transfers = vec![];
+        for (currency, balances) in self.balances() {
+            let mut owed: Vec<(&str, i64)> = balances
+                .iter()
+                .filter(|(_, cents)| **cents > 0)
+                .map(|(member, cents)| (*member, *cents))
+                .collect();
+            let mut owing: Vec<(&str, i64)> = balances
+                .iter()
+                .filter(|(_, cents)| **cents < 0)
+                .map(|(member, cents)| (*member, -cents))
+                .collect();
+            owed.sort_by_key(|(member, cents)| (std::cmp::Reverse(*cents), *member));
+            owing.sort_by_key(|(member, cents)| (std::cmp::Reverse(*cents), *member));
+
+            let (mut i, mut j) = (0, 0);
+            while i < owing.len() && j < owed.len() {
+                let cents = owing[i].1.min(owed[j].1);
+                transfers.push(Transfer {
+                    from: owing[i].0.to_string(),
+                    to: owed[j].0.to_string(),
+                    amount: Money { cents, currency },
+                });
+                owing[i].1 -= cents;
+                owed[j].1 -= cents;
+                if owing[i].1 == 0 {
+                    i += 1;
+                }
+                if owed[j].1 == 0 {
+                    j += 1;
+                }
+            }
+        }
+        transfers
+    }
+
+    fn settle(&self, console: &mut dyn Console) {
+        console.print("\n** Balances **");
+        let mut rows = vec![];
+        for (currency, balances) in self.balances() {
+            for (member, cents) in balances {
+                let amount = Money {
+                    cents: cents.abs(),
+                    currency,
+                };
+                let status = match cents {
+                    cents if cents > 0 => "is owed",
+                    cents if cents < 0 => "owes",
+                    _ => "even",
+                };
+                rows.push(vec![
+                    member.to_string(),
+                    status.to_string(),
+                    amount.to_string(),
+                ]);
+            }
+        }
+        if rows.is_empty() {
+            console.print("No payments on split bills yet");
+            return;
+        }
+        print_table(&["member", "", "amount"], &[2], &rows, console);
+
+        console.print("\n** Who owes whom **");
+        let transfers = self.settlement();
+        if transfers.is_empty() {
+            console.print("Everyone is even");
+        }
+        for transfer in transfers {
+            console.print(&format!(
+                "{} pays {} {}",
+                transfer.from, transfer.to, transfer.amount
+            ));
+        }
+    }
+}
+
+#[derive(Debug)]
+enum MenuOption {
+    Add,
+    View,
+    Remove,
+    Edit,
+    Due,
+    Pay,
+    History,
+    Budget,
+    Summary,
+    Undo,
+    Redo,
+    Members,
+    Split,
+    Settle,
+    Ledger,
+    Totals,
+}
+
+impl MenuOption {
+    fn show_menu(ledger: &str, console: &mut dyn Console) {
+        console.print(&format!("\n** Manage Bills ({} ledger) **", ledger));
+        console.print("'add' - Add bill");
+        console.print("'view' - View bills");
+        console.print("'edit' - Edit bill");
+        console.print("'remove' - Remove bill");
+        console.print("'due' - View upcoming and overdue bills");
+        console.print("'pay' - Pay bill");
+        console.print("'history' - View payment history");
+        console.print("'budget' - Set monthly budget for a category");
+        console.print("'summary' - View monthly spending by category");
+        console.print("'undo' - Undo last change");
+        console.print("'redo' - Redo last undone change");
+        console.print("'members' - Add or remove household members");
+        console.print("'split' - Split a bill between members");
+        console.print("'settle' - View who owes whom");
+        console.print("'ledger' - Create, switch or delete ledgers");
+        console.print("'totals' - View totals across all ledgers");
+        console.print("'quit' - Quit");
+        console.print("-------------------\n");
+    }
+
+    fn get_user_option(console: &mut dyn Console) -> Option<MenuOption> {
+        let input = match get_user_input(console) {
+            Some(input) => input,
+            // FIXME Error with just returning ()...
+            // Q: How to use None => return if the function expects to
+            // return a String? Getting an error...
+            // A: Looks like when the fn returns an Option, you can
+            // use None => return None, to return the correct type.
+            // NOTE return; is same as return ();
+            // None => return,  // Error
+            None => return None,
+        };
+
+        match input.as_str() {
+            "add" => Some(MenuOption::Add),
+            "view" => Some(MenuOption::View),
+            "edit" => Some(MenuOption::Edit),
+            "remove" => Some(MenuOption::Remove),
+            "due" => Some(MenuOption::Due),
+            "pay" => Some(MenuOption::Pay),
+            "history" => Some(MenuOption::History),
+            "budget" => Some(MenuOption::Budget),
+            "summary" => Some(MenuOption::Summary),
+            "undo" => Some(MenuOption::Undo),
+            "redo" => Some(MenuOption::Redo),
+            "members" => Some(MenuOption::Members),
+            "split" => Some(MenuOption::Split),
+            "settle" => Some(MenuOption::Settle),
+            "ledger" => Some(MenuOption::Ledger),
+            "totals" => Some(MenuOption::Totals),
+            _ => None,
+        }
+    }
+
+    // Could also consider returning a Result<(), String> based on whether
+    // the CRUD commands are successful. This would give me the added bonus
+    // of being able to use the ? operator on the CRUD methods as well.
+    fn process_option(option: MenuOption, ledgers: &mut Ledgers, console: &mut dyn Console) {
+        // Gather the name of the bill from user
+        // let name = get_user_input();
+
+        // Q: Where should I create the new Bills?
+        // A: For now I've initialized inside main so I can pass Bills
+        // and MenuOption to this function.
+        // UPDATE Every ledger has its own Bills now, the menu works on the current one
+        let ledger = match option {
+            MenuOption::Ledger => return ledgers.ledger_menu(console),
+            MenuOption::Totals => return ledgers.totals(console),
+            _ => ledgers.current_mut(),
+        };
+        let bills = &mut ledger.bills;
+        match option {
+            MenuOption::Add => Bills::add_bill_menu(bills, console),
+            MenuOption::View => Bills::view_bills_menu(bills, console),
+            MenuOption::Edit => Bills::edit_bill_menu(bills, console),
+            MenuOption::Remove => Bills::remove_bill_menu(bills, console),
+            MenuOption::Due => Bills::due_bills_menu(bills, console),
+            MenuOption::Pay => Bills::pay_bill_menu(bills, console),
+            MenuOption::History => Bills::history_menu(bills, console),
+            MenuOption::Budget => Bills::set_budget_menu(bills, console),
+            MenuOption::Summary => Bills::summary_menu(bills, console),
+            MenuOption::Undo => Bills::undo_menu(bills, console),
+            MenuOption::Redo => Bills::redo_menu(bills, console),
+            MenuOption::Members => Bills::members_menu(bills, console),
+            MenuOption::Split => Bills::split_bill_menu(bills, console),
+            MenuOption::Settle => Bills::settle(bills, console),
+            // NOTE Already handled above, since they work on every ledger
+            MenuOption::Ledger | MenuOption::Totals => (),
+        }
+
+        // NOTE Save after every menu option that can change the bills so nothing
+        // is lost if the program is killed instead of quit properly
+        match option {
+            MenuOption::View
+            | MenuOption::Due
+            | MenuOption::History
+            | MenuOption::Summary
+            | MenuOption::Settle => (),
+            _ => save_or_report(&ledger.file, bills, console),
+        }
+    }
+}
+
+/// How often a bill comes back around after it has been paid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Recurrence {
+    Once,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl Recurrence {
+    fn from_input(input: &str) -> Option<Recurrence> {
+        match input {
+            "once" => Some(Recurrence::Once),
+            "weekly" => Some(Recurrence::Weekly),
+            "monthly" => Some(Recurrence::Monthly),
+            "yearly" => Some(Recurrence::Yearly),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Recurrence::Once => "once",
+            Recurrence::Weekly => "weekly",
+            Recurrence::Monthly => "monthly",
+            Recurrence::Yearly => "yearly",
+        }
+    }
+
+    /// The next due date after `due`, or None for one-off bills.
+    fn next_due(&self, due: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Recurrence::Once => None,
+            Recurrence::Weekly => Some(due + Duration::weeks(1)),
+            Recurrence::Monthly => Some(add_months(due, 1)),
+            Recurrence::Yearly => Some(add_months(due, 12)),
+        }
+    }
+}
+
+/// What a bill is for, used to group bills against a monthly budget.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Category {
+    Rent,
+    Utilities,
+    Subscriptions,
+    /// Any other category, named by the user.
+    Custom(String),
+}
+
+impl Category {
+    /// Category used for bills saved before categories existed.
+    fn other() -> Category {
+        Category::Custom("other".to_string())
+    }
+
+    fn from_input(input: &str) -> Category {
+        match input {
+            "rent" => Category::Rent,
+            "utilities" => Category::Utilities,
+            "subscriptions" => Category::Subscriptions,
+            custom => Category::Custom(custom.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Category::Rent => f.write_str("rent"),
+            Category::Utilities => f.write_str("utilities"),
+            Category::Subscriptions => f.write_str("subscriptions"),
+            Category::Custom(name) => f.write_str(name),
+        }
+    }
+}
+
+/// A three letter ISO 4217 currency code, e.g. USD.
+// NOTE Stored as bytes instead of a String so it can be Copy
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Currency([u8; 3]);
+
+impl Currency {
+    /// Parses a currency code, ignoring case. Only checks that the code is
+    /// three letters, not that it's a currency that actually exists.
+    fn parse(code: &str) -> Result<Currency, MoneyError> {
+        let code = code.to_uppercase();
+        match code.as_bytes() {
+            [a, b, c] if code.chars().all(|ch| ch.is_ascii_alphabetic()) => {
+                Ok(Currency([*a, *b, *c]))
+            }
+            _ => Err(MoneyError::UnknownCurrency(code)),
+        }
+    }
+
+    /// Currency for a symbol written in front of an amount, e.g. "$".
+    fn from_symbol(symbol: char) -> Option<Currency> {
+        match symbol {
+            '$' => Some(Currency(*b"USD")),
+            '€' => Some(Currency(*b"EUR")),
+            '£' => Some(Currency(*b"GBP")),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // NOTE Only ever built from ASCII letters, so this can't fail
+        f.write_str(std::str::from_utf8(&self.0).unwrap_or("???"))
+    }
+}
+
+// NOTE Written by hand so {:?} shows "USD" instead of the raw bytes
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// Errors that may occur while parsing an amount of money.
+#[derive(Error, Debug, PartialEq)]
+enum MoneyError {
+    #[error("no amount given")]
+    Empty,
+    #[error("amounts cannot be negative")]
+    Negative,
+    #[error("amounts can have at most 2 decimal places")]
+    TooManyDecimals,
+    #[error("amount is too large")]
+    TooLarge,
+    #[error("not a valid amount: {0}")]
+    InvalidNumber(String),
+    #[error("not a valid currency: {0}")]
+    UnknownCurrency(String),
+    #[error("currency symbol and code don't match: {0}")]
+    MismatchedCurrency(String),
+}
+
+/// An exact amount of money, stored as whole cents (minor units) so that
+/// adding amounts together never drifts the way f64 does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Money {
+    cents: i64,
+    currency: Currency,
+}
+
+impl Money {
+    /// Parses amounts such as "12", "1234.5", "$1,234.56", "€20" or "20.00 eur".
+    /// Thousands separators must be in the right place, and an amount can
+    /// have at most 2 decimal places.
+    fn parse(input: &str) -> Result<Money, MoneyError> {
+        Money::parse_in(input, DEFAULT_CURRENCY)
+    }
+
+    /// Same as parse(), but amounts without a symbol or code are in `currency`.
+    fn parse_in(input: &str, currency: Currency) -> Result<Money, MoneyError> {
+        let default_currency = currency;
+        let mut number = input.trim();
+        if number.is_empty() {
+            return Err(MoneyError::Empty);
+        }
+        if number.starts_with('-') {
+            return Err(MoneyError::Negative);
+        }
+
+        // The currency can either be a symbol in front, or a code before or
+        // after the number separated by a space.
+        let mut currency = None;
+        if let Some(symbol) = number.chars().next() {
+            if let Some(symbol_currency) = Currency::from_symbol(symbol) {
+                currency = Some(symbol_currency);
+                number = number[symbol.len_utf8()..].trim_start();
+            }
+        }
+        if let Some((first, second)) = number.split_once(' ') {
+            let (code, rest) = if first.chars().all(|ch| ch.is_ascii_alphabetic()) {
+                (first, second)
+            } else {
+                (second, first)
+            };
+            let code = Currency::parse(code.trim())?;
+            match currency {
+                Some(symbol_currency) if symbol_currency != code => {
+                    return Err(MoneyError::MismatchedCurrency(input.trim().to_string()))
+                }
+                _ => currency = Some(code),
+            }
+            number = rest.trim();
+        }
+        if number.starts_with('-') {
+            return Err(MoneyError::Negative);
+        }
+
+        let invalid = || MoneyError::InvalidNumber(input.trim().to_string());
+        let (units, fraction) = match number.split_once('.') {
+            Some((units, fraction)) => (units, fraction),
+            None => (number, ""),
+        };
+        if fraction.len() > 2 {
+            return Err(if fraction.chars().all(|ch| ch.is_ascii_digit()) {
+                MoneyError::TooManyDecimals
+            } else {
+                invalid()
+            });
+        }
+        if !fraction.chars().all(|ch| ch.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        // Thousands separators: the first group is 1-3 digits, the rest are exactly 3.
+        let groups: Vec<&str> = units.split(',').collect();
+        let valid_groups = groups.iter().enumerate().all(|(i, group)| {
+            let len_ok = if i == 0 {
+                (1..=3).contains(&group.len()) || groups.len() == 1
+            } else {
+                group.len() == 3
+            };
+            len_ok && group.chars().all(|ch| ch.is_ascii_digit())
+        });
+        let units = groups.concat();
+        if units.is_empty() || !valid_groups {
+            return Err(invalid());
+        }
+        // NOTE Capping amounts at 12 digits keeps them (and any totals) far away
+        // from overflowing an i64
+        if units.trim_start_matches('0').len() > 12 {
+            return Err(MoneyError::TooLarge);
+        }
+        let units: i64 = units.parse().map_err(|_| invalid())?;
+        // "5" means 50 cents and "05" means 5 cents
+        let fraction: i64 = format!("{:0<2}", fraction).parse().map_err(|_| invalid())?;
+
+        Ok(Money {
+            cents: units * 100 + fraction,
+            currency: currency.unwrap_or(default_currency),
+        })
+    }
+}
+
+impl Money {
+    /// The amount without a currency, e.g. "1234.50". Used in the data file.
+    fn to_plain_string(self) -> String {
+        format!("{}.{:02}", self.cents / 100, self.cents % 100)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.to_plain_string(), self.currency)
+    }
+}
+
+/// Errors that may occur when paying a bill.
+#[derive(Error, Debug, PartialEq)]
+enum PaymentError {
+    #[error("payments must be more than zero")]
+    Zero,
+    #[error("payment is in {paid} but the bill is in {bill}")]
+    WrongCurrency { paid: Currency, bill: Currency },
+    #[error("payment is more than the {0} remaining")]
+    Overpaid(Money),
+    #[error("bill is already paid")]
+    AlreadyPaid,
+}
+
+/// A payment made towards a bill.
+#[derive(Debug, Clone, PartialEq)]
+struct Payment {
+    /// The day the payment was made.
+    date: NaiveDate,
+    amount: Money,
+    /// The due date of the bill at the time of payment, so payments can be
+    /// matched up with the period they were for after the bill rolls forward.
+    due: NaiveDate,
+    /// The member who paid, for payments on bills split between members.
+    by: Option<String>,
+}
+
+/// Errors that may occur while managing household members and splitting bills.
+#[derive(Error, Debug, PartialEq)]
+enum HouseholdError {
+    #[error("member names cannot be empty or contain , ; : | = [ or ]: {0:?}")]
+    InvalidMemberName(String),
+    #[error("{0} is already a member")]
+    MemberExists(String),
+    #[error("{0} is not a household member")]
+    UnknownMember(String),
+    #[error("{member} can't be removed, they are still part of {bill}")]
+    MemberInUse { member: String, bill: String },
+    #[error("unknown split: {0} (expected equal, percent or fixed)")]
+    UnknownSplit(String),
+    #[error("no members given")]
+    NoMembers,
+    #[error("expected a member followed by their share: {0:?}")]
+    InvalidShare(String),
+    #[error("{0} is listed more than once")]
+    DuplicateMember(String),
+    #[error("percentages add up to {0}%, not 100%")]
+    PercentTotal(String),
+    #[error("amounts add up to {total}, not the bill's {bill}")]
+    FixedTotal { total: Money, bill: Money },
+    #[error("invalid amount: {0}")]
+    InvalidAmount(#[from] MoneyError),
+}
+
+/// How a bill is shared between household members.
+#[derive(Debug, Clone, PartialEq)]
+enum Split {
+    /// Everyone pays the same.
+    Equal(Vec<String>),
+    /// Each member pays a percentage, in hundredths of a percent so that
+    /// 33.33% is 3333.
+    Percent(Vec<(String, i64)>),
+    /// Each member pays a fixed amount, adding up to the bill's amount.
+    Fixed(Vec<(String, Money)>),
+}
+
+/// Formats hundredths of a percent, e.g. 3333 as "33.33" and 5000 as "50".
+fn format_percent(hundredths: i64) -> String {
+    if hundredths % 100 == 0 {
+        (hundredths / 100).to_string()
+    } else {
+        format!("{}.{:02}", hundredths / 100, hundredths % 100)
+    }
+}
+
+/// Parses a percentage with at most 2 decimal places into hundredths of a percent.
+fn parse_percent(percent: &str) -> Option<i64> {
+    let percent = percent.trim().trim_end_matches('%');
+    let (units, fraction) = percent.split_once('.').unwrap_or((percent, ""));
+    let digits = |part: &str| part.chars().all(|ch| ch.is_ascii_digit());
+    if units.is_empty()
+        || units.len() > 3
+        || fraction.len() > 2
+        || !digits(units)
+        || !digits(fraction)
+    {
+        return None;
+    }
+    Some(units.parse::<i64>().ok()? * 100 + format!("{:0<2}", fraction).parse::<i64>().ok()?)
+}
+
+impl Split {
+    /// Parses a split typed in by the user. `shares` is a comma separated
+    /// list of members for an equal split ("alice, bob"), or of members and
+    /// their share otherwise ("alice 60, bob 40"). Amounts without a currency
+    /// are in `currency`.
+    fn from_input(kind: &str, shares: &str, currency: Currency) -> Result<Split, HouseholdError> {
+        let pieces: Vec<&str> = shares
+            .split(',')
+            .map(|piece| piece.trim())
+            .filter(|piece| !piece.is_empty())
+            .collect();
+        // NOTE Split on the last space, so member names can have spaces in them
+        let with_share = |piece: &&str| match piece.rsplit_once(char::is_whitespace) {
+            Some((name, share)) => Ok((name.trim().to_string(), share.to_string())),
+            None => Err(HouseholdError::InvalidShare(piece.to_string())),
+        };
+        match kind {
+            "equal" => Ok(Split::Equal(
+                pieces.iter().map(|piece| piece.to_string()).collect(),
+            )),
+            "percent" => pieces
+                .iter()
+                .map(|piece| {
+                    let (name, share) = with_share(piece)?;
+                    let percent = parse_percent(&share)
+                        .ok_or_else(|| HouseholdError::InvalidShare(piece.to_string()))?;
+                    Ok((name, percent))
+                })
+                .collect::<Result<Vec<(String, i64)>, HouseholdError>>()
+                .map(Split::Percent),
+            "fixed" => pieces
+                .iter()
+                .map(|piece| {
+                    let (name, share) = with_share(piece)?;
+                    Ok((name, Money::parse_in(&share, currency)?))
+                })
+                .collect::<Result<Vec<(String, Money)>, HouseholdError>>()
+                .map(Split::Fixed),
+            _ => Err(HouseholdError::UnknownSplit(kind.to_string())),
+        }
+    }
+
+    fn members(&self) -> Vec<&str> {
+        match self {
+            Split::Equal(members) => members.iter().map(|member| member.as_str()).collect(),
+            Split::Percent(shares) => shares.iter().map(|(member, _)| member.as_str()).collect(),
+            Split::Fixed(shares) => shares.iter().map(|(member, _)| member.as_str()).collect(),
+        }
+    }
+
+    fn members_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Split::Equal(members) => members.iter_mut().collect(),
+            Split::Percent(shares) => shares.iter_mut().map(|(member, _)| member).collect(),
+            Split::Fixed(shares) => shares.iter_mut().map(|(member, _)| member).collect(),
+        }
+    }
+
+    /// Each member's share relative to the others.
+    fn weights(&self) -> Vec<(&str, i64)> {
+        match self {
+            Split::Equal(members) => members.iter().map(|member| (member.as_str(), 1)).collect(),
+            Split::Percent(shares) => shares
+                .iter()
+                .map(|(member, percent)| (member.as_str(), *percent))
+                .collect(),
+            Split::Fixed(shares) => shares
+                .iter()
+                .map(|(member, amount)| (member.as_str(), amount.cents))
+                .collect(),
+        }
+    }
+
+    /// Checks the split has members, lists each of them once, and that the
+    /// shares add up for a bill of `amount`.
+    fn check(&self, amount: Money) -> Result<(), HouseholdError> {
+        let members = self.members();
+        if members.is_empty() {
+            return Err(HouseholdError::NoMembers);
+        }
+        for (i, member) in members.iter().enumerate() {
+            if members[..i]
+                .iter()
+                .any(|other| other.eq_ignore_ascii_case(member))
+            {
+                return Err(HouseholdError::DuplicateMember(member.to_string()));
+            }
+        }
+        match self {
+            Split::Equal(_) => Ok(()),
+            Split::Percent(shares) => {
+                let total: i64 = shares.iter().map(|(_, percent)| percent).sum();
+                if total == 10_000 {
+                    Ok(())
+                } else {
+                    Err(HouseholdError::PercentTotal(format_percent(total)))
+                }
+            }
+            Split::Fixed(shares) => {
+                let total = Money {
+                    cents: shares.iter().map(|(_, share)| share.cents).sum(),
+                    currency: shares[0].1.currency,
+                };
+                if total == amount
+                    && shares
+                        .iter()
+                        .all(|(_, share)| share.currency == amount.currency)
+                {
+                    Ok(())
+                } else {
+                    Err(HouseholdError::FixedTotal {
+                        total,
+                        bill: amount,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Divides `amount` between the members in proportion to their shares.
+    /// The shares always add up to exactly `amount`: any cents left over
+    /// from rounding down go to the members who lost the most to rounding.
+    fn shares(&self, amount: Money) -> Vec<(&str, Money)> {
+        let weights = self.weights();
+        let total: i64 = weights.iter().map(|(_, weight)| weight).sum();
+        if total == 0 {
+            return vec![];
+        }
+        // NOTE i128 so multiplying an amount by a weight can't overflow
+        let mut shares: Vec<(&str, i64, i128)> = weights
+            .into_iter()
+            .map(|(member, weight)| {
+                let exact = amount.cents as i128 * weight as i128;
+                (
+                    member,
+                    (exact / total as i128) as i64,
+                    exact % total as i128,
+                )
+            })
+            .collect();
+        let mut left = amount.cents - shares.iter().map(|(_, cents, _)| cents).sum::<i64>();
+        let mut order: Vec<usize> = (0..shares.len()).collect();
+        // NOTE sort_by_key is stable, so ties go to whoever is listed first
+        order.sort_by_key(|i| std::cmp::Reverse(shares[*i].2));
+        for i in order {
+            if left == 0 {
+                break;
+            }
+            shares[i].1 += 1;
+            left -= 1;
+        }
+        shares
+            .into_iter()
+            .map(|(member, cents, _)| (member, Money { cents, ..amount }))
+            .collect()
+    }
+
+    /// The split as it is written in the data file, e.g. "equal:alice|bob",
+    /// "percent:alice=60.00|bob=40.00" or "fixed:alice=700.00|bob=500.00".
+    fn to_field(&self) -> String {
+        let (kind, shares): (&str, Vec<String>) = match self {
+            Split::Equal(members) => ("equal", members.clone()),
+            Split::Percent(shares) => (
+                "percent",
+                shares
+                    .iter()
+                    .map(|(member, percent)| {
+                        format!("{}={}.{:02}", member, percent / 100, percent % 100)
+                    })
+                    .collect(),
+            ),
+            Split::Fixed(shares) => (
+                "fixed",
+                shares
+                    .iter()
+                    .map(|(member, amount)| format!("{}={}", member, amount.to_plain_string()))
+                    .collect(),
+            ),
+        };
+        format!("{}:{}", kind, shares.join("|"))
+    }
+
+    /// Parses a split written by to_field().
+    fn from_field(field: &str, currency: Currency) -> Result<Split, HouseholdError> {
+        let (kind, shares) = field
+            .split_once(':')
+            .ok_or_else(|| HouseholdError::UnknownSplit(field.to_string()))?;
+        // NOTE Turn it back into the "alice 60, bob 40" form typed into the menu
+        let shares: Vec<String> = shares
+            .split('|')
+            .map(|share| share.replace('=', " "))
+            .collect();
+        Split::from_input(kind, &shares.join(","), currency)
+    }
+}
+
+impl fmt::Display for Split {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shares: Vec<String> = match self {
+            Split::Equal(members) => return write!(f, "equally: {}", members.join(", ")),
+            Split::Percent(shares) => shares
+                .iter()
+                .map(|(member, percent)| format!("{} {}%", member, format_percent(*percent)))
+                .collect(),
+            Split::Fixed(shares) => shares
+                .iter()
+                .map(|(member, amount)| format!("{} {}", member, amount.to_plain_string()))
+                .collect(),
+        };
+        f.write_str(&shares.join(", "))
+    }
+}
+
+/// A payment from one member to another that settles up what they owe.
+#[derive(Debug, PartialEq)]
+struct Transfer {
+    from: String,
+    to: String,
+    amount: Money,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Bill {
+    /// Never changes once the bill is added, even if the bill is renamed.
+    id: u64,
+    name: String,
+    amount: Money,
+    due: NaiveDate,
+    recurrence: Recurrence,
+    /// Only one-off bills are ever marked as paid, recurring bills roll
+    /// forward to their next due date instead.
+    paid: bool,
+    /// Every payment made on this bill, oldest first.
+    payments: Vec<Payment>,
+    category: Category,
+    /// How the bill is shared between household members, if it is.
+    split: Option<Split>,
+}
+
+impl Bill {
+    fn new(console: &mut dyn Console) -> Option<Self> {
+        // NOTE Skip formatting so the comments below stay lined up with the match arms
+        #[rustfmt::skip]
+        let name = match Bill::get_bill_name(console) {
+            Some(name) => name,
+            None => return None, // Error: return type is not () unit type (it's 'Bill')
+            // Q: Do I need to make new() return an Option or Result? Again, I'm trying
+            // to give user a chance to cancel/go back in the menu by making get_user_input()
+            // return an Option type.
+            // A: Yes! Make this return Option<Self> and then that enables me to use the
             // return None variants. If it returns None, then menu cancels.
         };
 
-        // Q: How to use this new get_bill_amount() fn without have a self to reference?
-        // Remove the &self parameter from get_bill_amount() fn?
-        // A: Yea, removing the &self param AND making new() -> Option<Self>, instead of
-        // just Self, seemed to help!
-        let amount = match Bill::get_bill_amount() {
-            Some(inner_amount) => inner_amount,
-            None => return None,
+        // Q: How to use this new get_bill_amount() fn without have a self to reference?
+        // Remove the &self parameter from get_bill_amount() fn?
+        // A: Yea, removing the &self param AND making new() -> Option<Self>, instead of
+        // just Self, seemed to help!
+        let amount = match Bill::get_bill_amount(console) {
+            Some(inner_amount) => inner_amount,
+            None => return None,
+        };
+        let due = Bill::get_bill_due_date(console)?;
+        let recurrence = Bill::get_bill_recurrence(console)?;
+        let category = Bill::get_bill_category(console)?;
+
+        // Already have return None (above), so should be able to just return
+        // the Some() variant for the entire new() fn.
+        Some(Self {
+            // NOTE The real id is handed out by Bills::add_bill()
+            id: 0,
+            name,
+            amount,
+            due,
+            recurrence,
+            paid: false,
+            payments: vec![],
+            category,
+            split: None,
+        })
+    }
+
+    fn get_bill_name(console: &mut dyn Console) -> Option<String> {
+        loop {
+            console.print("Enter bill name:");
+            let name = get_user_input_keep_case(console)?;
+            // NOTE Commas would break the columns in the data file
+            if name.contains(',') {
+                console.print("Bill names cannot contain commas");
+            } else {
+                return Some(name);
+            }
+        }
+    }
+
+    /// This bill updated with the amount, due date, recurrence and category
+    /// of `new`. The id, name, payments and split stay the same.
+    fn merged(&self, new: Bill) -> Bill {
+        Bill {
+            id: self.id,
+            name: self.name.clone(),
+            payments: self.payments.clone(),
+            split: self.split.clone(),
+            ..new
+        }
+    }
+
+    /// `new` in place of this bill, keeping only this bill's id.
+    fn replaced(&self, new: Bill) -> Bill {
+        Bill { id: self.id, ..new }
+    }
+
+    fn get_bill_category(console: &mut dyn Console) -> Option<Category> {
+        loop {
+            console.print("Enter category (rent, utilities, subscriptions, or your own):");
+            let category = get_user_input(console)?;
+            if category.contains(',') {
+                console.print("Categories cannot contain commas");
+            } else {
+                return Some(Category::from_input(&category));
+            }
+        }
+    }
+
+    fn get_payment_date(console: &mut dyn Console) -> Option<NaiveDate> {
+        loop {
+            console.print("Enter payment date (YYYY-MM-DD or 'today'):");
+            let date = get_user_input(console)?;
+            if date == "today" {
+                return Some(today());
+            }
+            match NaiveDate::parse_from_str(&date, DATE_FORMAT) {
+                Ok(date) => return Some(date),
+                Err(_) => console.print(&format!(
+                    "Please enter a valid date, e.g. {}",
+                    today().format(DATE_FORMAT)
+                )),
+            }
+        }
+    }
+
+    /// How much is still owed for the current due date.
+    fn remaining(&self) -> Money {
+        if self.paid {
+            return Money {
+                cents: 0,
+                ..self.amount
+            };
+        }
+        let paid: i64 = self
+            .payments
+            .iter()
+            .filter(|payment| payment.due == self.due)
+            .map(|payment| payment.amount.cents)
+            .sum();
+        Money {
+            // NOTE The amount can be edited down after a partial payment
+            cents: (self.amount.cents - paid).max(0),
+            ..self.amount
+        }
+    }
+
+    /// Records a payment made on `date`, by the member `by` if the bill is
+    /// split. Once nothing remains for the current due date the bill is
+    /// marked as paid.
+    fn pay(
+        &mut self,
+        date: NaiveDate,
+        amount: Money,
+        by: Option<String>,
+    ) -> Result<(), PaymentError> {
+        let remaining = self.remaining();
+        if self.paid {
+            return Err(PaymentError::AlreadyPaid);
+        }
+        if amount.currency != remaining.currency {
+            return Err(PaymentError::WrongCurrency {
+                paid: amount.currency,
+                bill: remaining.currency,
+            });
+        }
+        if amount.cents == 0 {
+            return Err(PaymentError::Zero);
+        }
+        if amount.cents > remaining.cents {
+            return Err(PaymentError::Overpaid(remaining));
+        }
+
+        self.payments.push(Payment {
+            date,
+            amount,
+            due: self.due,
+            by,
+        });
+        if amount.cents == remaining.cents {
+            self.mark_paid();
+        }
+        Ok(())
+    }
+
+    fn get_bill_due_date(console: &mut dyn Console) -> Option<NaiveDate> {
+        loop {
+            console.print("Enter due date (YYYY-MM-DD):");
+            let due = get_user_input(console)?;
+            match NaiveDate::parse_from_str(&due, DATE_FORMAT) {
+                Ok(due) => return Some(due),
+                Err(_) => console.print(&format!(
+                    "Please enter a valid date, e.g. {}",
+                    today().format(DATE_FORMAT)
+                )),
+            }
+        }
+    }
+
+    fn get_bill_recurrence(console: &mut dyn Console) -> Option<Recurrence> {
+        loop {
+            console.print("How often is this bill due? (once, weekly, monthly, yearly)");
+            let recurrence = get_user_input(console)?;
+            match Recurrence::from_input(&recurrence) {
+                Some(recurrence) => return Some(recurrence),
+                None => console.print("Please enter once, weekly, monthly or yearly"),
+            }
+        }
+    }
+
+    /// The first date this bill was due. Paying a recurring bill moves its
+    /// due date forward, so the payments remember the earlier ones.
+    fn first_due(&self) -> NaiveDate {
+        self.payments
+            .iter()
+            .map(|payment| payment.due)
+            .fold(self.due, NaiveDate::min)
+    }
+
+    /// How many times this bill falls due in the month starting on `month`.
+    /// Recurring bills repeat from their first due date onwards.
+    fn times_due_in_month(&self, month: NaiveDate) -> i64 {
+        let next_month = add_months(month, 1);
+        let first_due = self.first_due();
+        // NOTE Nothing is due in the months before the bill's first due date
+        if first_due >= next_month {
+            return 0;
+        }
+        match self.recurrence {
+            Recurrence::Once if self.due >= month && self.due < next_month => 1,
+            Recurrence::Once => 0,
+            Recurrence::Weekly => {
+                // The first day in the month that falls on the same weekday as the
+                // due date, or the first due date itself if that's later
+                let first = month + Duration::days((self.due - month).num_days().rem_euclid(7));
+                let first = first.max(first_due);
+                ((next_month - first).num_days() + 6) / 7
+            }
+            Recurrence::Monthly => 1,
+            Recurrence::Yearly if first_due.month() == month.month() => 1,
+            Recurrence::Yearly => 0,
+        }
+    }
+
+    /// Marks the bill as paid. Recurring bills move on to their next due
+    /// date, one-off bills are flagged as paid.
+    fn mark_paid(&mut self) {
+        match self.recurrence.next_due(self.due) {
+            Some(next_due) => self.due = next_due,
+            None => self.paid = true,
+        }
+    }
+
+    fn get_bill_amount(console: &mut dyn Console) -> Option<Money> {
+        // Method 1: Works but panics:
+        // let amount = get_user_input();
+        // let amount: f64 = amount
+        //     .parse()
+        //     .expect("Failed to convert amount from String to f64");
+
+        // Method 2: Handle the error from parse() instead of using expect()
+        // Otherwise it panics and breaks the program
+        // Q: How to handle parse Err variant without panicking?
+        // The Err(_) variant can't just return a print because it expects f64
+        // A: The trick is to use Result<f64, ParseFloatError> when using parse()!
+        // Put it inside a loop
+        // UPDATE Amounts are Money now instead of f64, which has its own parse()
+        loop {
+            console.print("Enter bill amount (e.g. 12.34, $1,234.56 or 20 EUR):");
+            let amount = match get_user_input(console) {
+                Some(amount) => amount,
+                None => return None,
+            };
+            // BROKEN:
+            // let amount: Result<f64, _> = match amount.parse() {
+            //     Ok(inner_amount) => {
+            //         let amount: f64 = inner_amount;
+            //         return Self { name, amount};
+            //     },
+            //     Err(_) => println!("Please enter a valid number")
+            // };
+            // WORKS:
+            // NOTE Trick is to return Result<Money, _> instead of Money
+            let parsed_amount: Result<Money, _> = Money::parse(&amount);
+            match parsed_amount {
+                Ok(inner_amount) => {
+                    // NOTE MUST 'return' from inside loop, otherwise infinite!
+                    return Some(inner_amount);
+                }
+                Err(e) => {
+                    console.print(&format!("Please enter a valid amount: {}", e));
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Errors that may occur while parsing the bills data file.
+#[derive(Error, Debug)]
+enum ParseError {
+    #[error("invalid amount: {0}")]
+    InvalidAmount(#[from] MoneyError),
+    #[error("due date must look like YYYY-MM-DD: {0}")]
+    InvalidDueDate(#[from] chrono::ParseError),
+    #[error("unknown recurrence: {0}")]
+    InvalidRecurrence(String),
+    #[error("paid must be true or false: {0}")]
+    InvalidPaid(#[from] std::str::ParseBoolError),
+    #[error("invalid payment: {0}")]
+    InvalidPayment(String),
+    #[error("invalid id: {0}")]
+    InvalidId(#[from] std::num::ParseIntError),
+    #[error("invalid split: {0}")]
+    InvalidSplit(#[from] HouseholdError),
+    #[error("invalid ledger: {0}")]
+    InvalidLedger(#[from] LedgerError),
+    #[error("missing field: {0}")]
+    MissingField(String),
+}
+
+/// Parses a single bill line, e.g. "electric,55.50,2021-10-01,monthly,false,USD,".
+fn parse_bill(line: &str) -> Result<Bill, ParseError> {
+    let fields: Vec<&str> = line.split(',').collect();
+
+    let name = match fields.first().filter(|name| !name.is_empty()) {
+        Some(name) => name.to_string(),
+        None => return Err(ParseError::MissingField("name".to_owned())),
+    };
+
+    // NOTE The currency column was added after the others, so files saved
+    // before then have no currency and use the default
+    let currency = match fields.get(5).filter(|currency| !currency.is_empty()) {
+        Some(currency) => Currency::parse(currency)?,
+        None => DEFAULT_CURRENCY,
+    };
+
+    let amount = match fields.get(1).filter(|amount| !amount.is_empty()) {
+        Some(amount) => Money::parse_in(amount, currency)?,
+        None => return Err(ParseError::MissingField("amount".to_owned())),
+    };
+
+    let due = match fields.get(2).filter(|due| !due.is_empty()) {
+        Some(due) => NaiveDate::parse_from_str(due, DATE_FORMAT)?,
+        None => return Err(ParseError::MissingField("due".to_owned())),
+    };
+
+    let recurrence = match fields.get(3).filter(|recurrence| !recurrence.is_empty()) {
+        Some(recurrence) => match Recurrence::from_input(recurrence) {
+            Some(recurrence) => recurrence,
+            None => return Err(ParseError::InvalidRecurrence(recurrence.to_string())),
+        },
+        None => return Err(ParseError::MissingField("recurrence".to_owned())),
+    };
+
+    let paid = match fields.get(4).filter(|paid| !paid.is_empty()) {
+        Some(paid) => paid.parse()?,
+        None => false,
+    };
+
+    let payments = match fields.get(6).filter(|payments| !payments.is_empty()) {
+        Some(payments) => payments
+            .split(';')
+            .map(|payment| parse_payment(payment, currency))
+            .collect::<Result<Vec<Payment>, ParseError>>()?,
+        None => vec![],
+    };
+
+    let category = match fields.get(7).filter(|category| !category.is_empty()) {
+        Some(category) => Category::from_input(category),
+        None => Category::other(),
+    };
+
+    // NOTE Files saved before bills had ids have no id column, which is left as 0
+    // here so parse_bills() can give the bill a new one
+    let id = match fields.get(8).filter(|id| !id.is_empty()) {
+        Some(id) => id.parse()?,
+        None => 0,
+    };
+
+    let split = match fields.get(9).filter(|split| !split.is_empty()) {
+        Some(split) => Some(Split::from_field(split, currency)?),
+        None => None,
+    };
+
+    Ok(Bill {
+        id,
+        name,
+        amount,
+        due,
+        recurrence,
+        paid,
+        payments,
+        category,
+        split,
+    })
+}
+
+/// Parses a single budget line, e.g. "utilities,150.00,USD".
+fn parse_budget(line: &str) -> Result<(Category, Money), ParseError> {
+    let fields: Vec<&str> = line.split(',').collect();
+
+    let category = match fields.first().filter(|category| !category.is_empty()) {
+        Some(category) => Category::from_input(category),
+        None => return Err(ParseError::MissingField("category".to_owned())),
+    };
+
+    let currency = match fields.get(2).filter(|currency| !currency.is_empty()) {
+        Some(currency) => Currency::parse(currency)?,
+        None => DEFAULT_CURRENCY,
+    };
+
+    let budget = match fields.get(1).filter(|budget| !budget.is_empty()) {
+        Some(budget) => Money::parse_in(budget, currency)?,
+        None => return Err(ParseError::MissingField("budget".to_owned())),
+    };
+
+    Ok((category, budget))
+}
+
+/// Parses a single payment from the payments column, e.g. "2021-10-01:25.00:2021-10-05"
+/// (the day it was paid, the amount, and the due date it was paid towards),
+/// followed by ":alice" when the payment was made by a member.
+fn parse_payment(payment: &str, currency: Currency) -> Result<Payment, ParseError> {
+    let fields: Vec<&str> = payment.split(':').collect();
+    match fields.as_slice() {
+        [date, amount, due] | [date, amount, due, _] => Ok(Payment {
+            date: NaiveDate::parse_from_str(date, DATE_FORMAT)?,
+            amount: Money::parse_in(amount, currency)?,
+            due: NaiveDate::parse_from_str(due, DATE_FORMAT)?,
+            by: fields.get(3).map(|by| by.to_string()),
+        }),
+        _ => Err(ParseError::InvalidPayment(payment.to_string())),
+    }
+}
+
+/// Marks the bills section in parse_bills(), which has no header of its own to match.
+const BILLS_SECTION: &str = "";
+
+/// Parses the entire bills file. Lines that cannot be parsed are reported
+/// and skipped, so a single bad line doesn't lose every other bill.
+/// Returns the bills along with how many lines were skipped.
+fn parse_bills(contents: String, console: &mut dyn Console) -> (Bills, usize) {
+    let mut bills = Bills::new();
+    let mut skipped = 0;
+    // NOTE The budgets and then the members come after the bills, each
+    // starting with their own header line
+    let mut section = BILLS_SECTION;
+    let mut parsed_bills = vec![];
+    // NOTE Skip the first line since it's the "name,amount,..." header
+    for (num, line) in contents.lines().enumerate().skip(1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line == BUDGETS_HEADER || line == MEMBERS_HEADER || line == LEDGERS_HEADER {
+            section = line;
+            continue;
+        }
+        // NOTE Bill and budget lines always have commas, so the old header is
+        // only a header before the members section. After it, it's a member's
+        // or ledger's name
+        if line == OLD_MEMBERS_HEADER && section != MEMBERS_HEADER && section != LEDGERS_HEADER {
+            section = MEMBERS_HEADER;
+            continue;
+        }
+        // NOTE Loading isn't a change the user made, so skip the undo history
+        let parsed = match section {
+            BUDGETS_HEADER => parse_budget(line)
+                .map(|(category, budget)| bills.replace_budget(&category, Some(budget))),
+            MEMBERS_HEADER => {
+                bills.replace_member(line.trim(), true);
+                Ok(())
+            }
+            LEDGERS_HEADER => check_ledger_name(line)
+                .map(|name| {
+                    bills.ledgers.insert(name);
+                })
+                .map_err(ParseError::from),
+            _ => parse_bill(line).map(|bill| parsed_bills.push(bill)),
+        };
+        if let Err(e) = parsed {
+            skipped += 1;
+            console.print(&format!(
+                "error on line number {}: {}\n  > \"{}\"\n",
+                num + 1,
+                e,
+                line
+            ));
+        }
+    }
+
+    // NOTE Bills without an id (or with one that's already taken) get a new
+    // one after the highest id in the file
+    bills.next_id = parsed_bills.iter().map(|bill| bill.id).max().unwrap_or(0) + 1;
+    for mut bill in parsed_bills {
+        if bill.id == 0 || bills.inner.contains_key(&bill.id) {
+            bill.id = bills.next_id;
+            bills.next_id += 1;
+        }
+        bills.replace_bill(bill.id, Some(bill));
+    }
+    (bills, skipped)
+}
+
+/// Loads the bills from a file.
+fn load_bills(data_file: &Path, console: &mut dyn Console) -> io::Result<(Bills, usize)> {
+    let mut file = File::open(data_file)?;
+
+    let mut buffer = String::new();
+    file.read_to_string(&mut buffer)?;
+
+    Ok(parse_bills(buffer, console))
+}
+
+/// Saves the bills to disk, replacing the previous contents of the file.
+fn save_bills(data_file: &Path, bills: &Bills) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(data_file)?;
+
+    file.write_all(b"name,amount,due,recurrence,paid,currency,payments,category,id,split\n")?;
+    for bill in bills.to_sorted_vec() {
+        let payments: Vec<String> = bill
+            .payments
+            .iter()
+            .map(|payment| {
+                let by = match &payment.by {
+                    Some(by) => format!(":{}", by),
+                    None => String::new(),
+                };
+                format!(
+                    "{}:{}:{}{}",
+                    payment.date.format(DATE_FORMAT),
+                    payment.amount.to_plain_string(),
+                    payment.due.format(DATE_FORMAT),
+                    by
+                )
+            })
+            .collect();
+        let line = format!(
+            "{},{},{},{},{},{},{},{},{},{}\n",
+            bill.name,
+            bill.amount.to_plain_string(),
+            bill.due.format(DATE_FORMAT),
+            bill.recurrence.as_str(),
+            bill.paid,
+            bill.amount.currency,
+            payments.join(";"),
+            bill.category,
+            bill.id,
+            bill.split.as_ref().map(Split::to_field).unwrap_or_default()
+        );
+        file.write_all(line.as_bytes())?;
+    }
+
+    if !bills.budgets.is_empty() {
+        file.write_all(format!("\n{}\n", BUDGETS_HEADER).as_bytes())?;
+        for (category, budget) in bills.budgets.iter() {
+            let line = format!(
+                "{},{},{}\n",
+                category,
+                budget.to_plain_string(),
+                budget.currency
+            );
+            file.write_all(line.as_bytes())?;
+        }
+    }
+
+    if !bills.members.is_empty() {
+        file.write_all(format!("\n{}\n", MEMBERS_HEADER).as_bytes())?;
+        for member in bills.members.iter() {
+            file.write_all(format!("{}\n", member).as_bytes())?;
+        }
+    }
+
+    if !bills.ledgers.is_empty() {
+        file.write_all(format!("\n{}\n", LEDGERS_HEADER).as_bytes())?;
+        for ledger in bills.ledgers.iter() {
+            file.write_all(format!("{}\n", ledger).as_bytes())?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Saves the bills, printing (rather than returning) any error so the
+/// interactive menu keeps running.
+fn save_or_report(data_file: &Path, bills: &Bills, console: &mut dyn Console) {
+    if let Err(e) = save_bills(data_file, bills) {
+        console.print(&format!(
+            "Unable to save bills to {}: {}",
+            data_file.display(),
+            e
+        ));
+    }
+}
+
+/// Loads the saved bills for startup. A missing file just means there are
+/// no bills yet. If some lines are corrupt, the original file is copied to
+/// a ".bak" file first so the skipped lines can still be recovered by hand.
+// NOTE Any other error is returned instead of starting with no bills, since
+// the first save would then replace everything in the file with nothing
+fn load_or_default(data_file: &Path, console: &mut dyn Console) -> io::Result<Bills> {
+    match load_bills(data_file, console) {
+        Ok((bills, 0)) => Ok(bills),
+        Ok((bills, skipped)) => {
+            let mut backup = data_file.as_os_str().to_owned();
+            backup.push(".bak");
+            match fs::copy(data_file, &backup) {
+                Ok(_) => console.print(&format!(
+                    "Skipped {} bad line(s), original file backed up to {}",
+                    skipped,
+                    Path::new(&backup).display()
+                )),
+                Err(e) => console.print(&format!(
+                    "Skipped {} bad line(s), backup failed: {}",
+                    skipped, e
+                )),
+            }
+            Ok(bills)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            console.print(&format!(
+                "No saved bills found at {}, starting fresh",
+                data_file.display()
+            ));
+            Ok(Bills::new())
+        }
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!("unable to load bills from {}: {}", data_file.display(), e),
+        )),
+    }
+}
+
+/// Errors that may occur while creating, switching or deleting ledgers.
+#[derive(Error, Debug)]
+enum LedgerError {
+    #[error("ledger names can only have lowercase letters, numbers and dashes: {0:?}")]
+    InvalidName(String),
+    #[error("there is already a ledger named {0}")]
+    Exists(String),
+    #[error("no ledger named {0}")]
+    NotFound(String),
+    #[error("the {0} ledger can't be deleted")]
+    Main(String),
+    #[error("{0} is the name of the main ledger")]
+    Reserved(String),
+    #[error("the {0} ledger is in use, switch to another ledger first")]
+    InUse(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A named set of bills, saved to its own file.
+#[derive(Debug)]
+struct Ledger {
+    file: PathBuf,
+    bills: Bills,
+}
+
+/// Every ledger, e.g. one for home and one for the office. The main ledger
+/// is saved in the data file, and the others are saved next to it with the
+/// ledger's name added, so "home" is saved in "p1_data_home.csv".
+#[derive(Debug)]
+struct Ledgers {
+    data_file: PathBuf,
+    inner: BTreeMap<String, Ledger>,
+    /// Name of the ledger the menu and commands work on.
+    current: String,
+}
+
+impl Ledgers {
+    /// Loads the main ledger and the other ledgers it lists.
+    fn load(data_file: &Path, console: &mut dyn Console) -> io::Result<Ledgers> {
+        let mut ledgers = Ledgers {
+            data_file: data_file.to_path_buf(),
+            inner: BTreeMap::new(),
+            current: MAIN_LEDGER.to_string(),
+        };
+        let main = Ledger {
+            file: data_file.to_path_buf(),
+            bills: load_or_default(data_file, console)?,
+        };
+        ledgers.inner.insert(MAIN_LEDGER.to_string(), main);
+
+        // NOTE Only the ledgers listed in the main file are loaded, so other
+        // files next to it (like editor backups) are never mistaken for ledgers
+        let names = ledgers.inner[MAIN_LEDGER].bills.ledgers.clone();
+        for name in names {
+            let file = ledgers.ledger_file(&name);
+            let bills = load_or_default(&file, console)?;
+            ledgers.inner.insert(name, Ledger { file, bills });
+        }
+        Ok(ledgers)
+    }
+
+    /// The file a ledger is saved in.
+    fn ledger_file(&self, name: &str) -> PathBuf {
+        if name == MAIN_LEDGER {
+            return self.data_file.clone();
+        }
+        let stem = self
+            .data_file
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let file_name = match self.data_file.extension() {
+            Some(extension) => format!("{}_{}.{}", stem, name, extension.to_string_lossy()),
+            None => format!("{}_{}", stem, name),
+        };
+        self.data_file.with_file_name(file_name)
+    }
+
+    fn current(&self) -> &Ledger {
+        // NOTE current is only ever set to a ledger that exists, and the
+        // current ledger can't be deleted
+        &self.inner[&self.current]
+    }
+
+    fn current_mut(&mut self) -> &mut Ledger {
+        self.inner
+            .get_mut(&self.current)
+            .expect("the current ledger always exists")
+    }
+
+    /// Creates an empty ledger, saving it straight away so it's there next time.
+    /// If the ledger's file already exists, its bills are kept instead.
+    fn create(&mut self, name: &str, console: &mut dyn Console) -> Result<(), LedgerError> {
+        let name = check_ledger_name(name)?;
+        if self.inner.contains_key(&name) {
+            return Err(LedgerError::Exists(name));
+        }
+        let file = self.ledger_file(&name);
+        let ledger = Ledger {
+            bills: load_or_default(&file, console)?,
+            file,
+        };
+        save_bills(&ledger.file, &ledger.bills)?;
+        self.inner.insert(name.clone(), ledger);
+        self.main_mut().bills.ledgers.insert(name);
+        self.save_main()
+    }
+
+    fn main_mut(&mut self) -> &mut Ledger {
+        self.inner
+            .get_mut(MAIN_LEDGER)
+            .expect("the main ledger always exists")
+    }
+
+    /// Saves the main ledger, which lists the other ledgers.
+    fn save_main(&self) -> Result<(), LedgerError> {
+        let main = &self.inner[MAIN_LEDGER];
+        Ok(save_bills(&main.file, &main.bills)?)
+    }
+
+    fn switch(&mut self, name: &str) -> Result<(), LedgerError> {
+        let name = name.trim().to_lowercase();
+        if !self.inner.contains_key(&name) {
+            return Err(LedgerError::NotFound(name));
+        }
+        self.current = name;
+        Ok(())
+    }
+
+    /// Finds a ledger that can be deleted, which is any ledger other than
+    /// the main one and the one in use.
+    fn deletable(&self, name: &str) -> Result<&Ledger, LedgerError> {
+        let name = name.trim().to_lowercase();
+        if name == MAIN_LEDGER {
+            return Err(LedgerError::Main(name));
+        }
+        if name == self.current {
+            return Err(LedgerError::InUse(name));
+        }
+        self.inner.get(&name).ok_or(LedgerError::NotFound(name))
+    }
+
+    /// Deletes a ledger along with its file.
+    fn delete(&mut self, name: &str) -> Result<Ledger, LedgerError> {
+        self.deletable(name)?;
+        let name = name.trim().to_lowercase();
+        let ledger = self
+            .inner
+            .remove(&name)
+            .ok_or_else(|| LedgerError::NotFound(name.clone()))?;
+        self.main_mut().bills.ledgers.remove(&name);
+        self.save_main()?;
+        match fs::remove_file(&ledger.file) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(ledger),
+        }
+    }
+
+    fn ledger_menu(&mut self, console: &mut dyn Console) {
+        console.print("\n** Ledgers **");
+        for (name, ledger) in self.inner.iter() {
+            let marker = if *name == self.current { "*" } else { " " };
+            console.print(&format!(
+                "{} {} ({} bill(s))",
+                marker,
+                name,
+                ledger.bills.inner.len()
+            ));
+        }
+        console.print("Enter 'new <name>', 'switch <name>' or 'delete <name>':");
+        let input = match get_user_input(console) {
+            Some(input) => input,
+            None => return,
+        };
+        let (action, name) = input.split_once(' ').unwrap_or((&input, ""));
+        let name = name.trim();
+        let result = match action {
+            "new" => self
+                .create(name, console)
+                .and_then(|()| self.switch(name))
+                .map(|()| console.print(&format!("Created ledger {}, now using it", self.current))),
+            "switch" => self
+                .switch(name)
+                .map(|()| console.print(&format!("Now using ledger {}", self.current))),
+            "delete" => {
+                let bills = match self.deletable(name) {
+                    Ok(ledger) => ledger.bills.inner.len(),
+                    Err(e) => {
+                        console.print(&e.to_string());
+                        return;
+                    }
+                };
+                console.print(&format!(
+                    "Delete ledger {} and its {} bill(s)? This can't be undone (yes/no)",
+                    name, bills
+                ));
+                if get_user_input(console).as_deref() != Some("yes") {
+                    console.print("Kept ledger");
+                    return;
+                }
+                self.delete(name)
+                    .map(|_| console.print(&format!("Deleted ledger {}", name)))
+            }
+            _ => {
+                console.print("Please enter new, switch or delete followed by a name");
+                return;
+            }
+        };
+        if let Err(e) = result {
+            console.print(&e.to_string());
+        }
+    }
+
+    /// Shows how many bills each ledger has and what they add up to, along
+    /// with the totals across every ledger.
+    fn totals(&self, console: &mut dyn Console) {
+        console.print("\n** Totals across ledgers **");
+        let join = |totals: &[Money]| -> String {
+            let totals: Vec<String> = totals.iter().map(|total| total.to_string()).collect();
+            totals.join(", ")
+        };
+        let mut all: BTreeMap<Currency, i64> = BTreeMap::new();
+        let mut bills = 0;
+        let mut rows = vec![];
+        for (name, ledger) in self.inner.iter() {
+            let totals = ledger.bills.totals();
+            for total in totals.iter() {
+                *all.entry(total.currency).or_insert(0) += total.cents;
+            }
+            bills += ledger.bills.inner.len();
+            rows.push(vec![
+                name.clone(),
+                ledger.bills.inner.len().to_string(),
+                join(&totals),
+            ]);
+        }
+        let all: Vec<Money> = all
+            .into_iter()
+            .map(|(currency, cents)| Money { cents, currency })
+            .collect();
+        rows.push(vec!["all".to_string(), bills.to_string(), join(&all)]);
+        print_table(&["ledger", "bills", "total"], &[1], &rows, console);
+    }
+}
+
+/// Checks a ledger name can be used in a file name, returning it lowercased.
+fn check_ledger_name(name: &str) -> Result<String, LedgerError> {
+    let name = name.trim().to_lowercase();
+    let valid = name
+        .chars()
+        .all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '-');
+    if name.is_empty() || !valid {
+        return Err(LedgerError::InvalidName(name));
+    }
+    if name == MAIN_LEDGER {
+        return Err(LedgerError::Reserved(name));
+    }
+    Ok(name)
+}
+
+/// Errors that may occur while importing a bank statement. MissingColumn
+/// stops the whole import, the rest only skip the row they happened on.
+#[derive(Error, Debug)]
+enum ImportError {
+    #[error("statement has no header line")]
+    EmptyStatement,
+    #[error("statement has no {0} column")]
+    MissingColumn(String),
+    #[error("missing {0}")]
+    MissingField(String),
+    #[error("date doesn't match the format {format}: {date}")]
+    InvalidDate { date: String, format: String },
+    #[error("invalid amount: {0}")]
+    InvalidAmount(#[from] MoneyError),
+    #[error("unable to pay {name}: {error}")]
+    Payment { name: String, error: PaymentError },
+    #[error("{0} is split between members, use --by to say who paid")]
+    MissingPayer(String),
+}
+
+/// How to read a bank statement, and what to use for bills it creates.
+#[derive(Debug)]
+struct ImportOptions {
+    /// Each column is either a header name (ignoring case) or a 1-based column number.
+    date_column: String,
+    payee_column: String,
+    amount_column: String,
+    /// chrono format of the dates, e.g. "%m/%d/%Y".
+    date_format: String,
+    recurrence: Recurrence,
+    category: Category,
+    /// The member who made the payments, needed for bills split between members.
+    by: Option<String>,
+}
+
+/// The outcome of importing a bank statement.
+#[derive(Debug, Default)]
+struct ImportReport {
+    /// Rows recorded as payments on bills that already existed.
+    paid: usize,
+    /// Names of the bills created for payees that didn't match a bill.
+    created: Vec<String>,
+    /// Line number and reason for every row that wasn't imported.
+    skipped: Vec<(usize, ImportError)>,
+}
+
+impl ImportReport {
+    fn print(&self, console: &mut dyn Console) {
+        console.print(&format!(
+            "Imported {} payment(s) on existing bills and created {} new bill(s)",
+            self.paid,
+            self.created.len()
+        ));
+        for name in self.created.iter() {
+            console.print(&format!("  new bill: {}", name));
+        }
+        if !self.skipped.is_empty() {
+            console.print(&format!("Skipped {} row(s):", self.skipped.len()));
+        }
+        for (line, e) in self.skipped.iter() {
+            console.print(&format!("  line {}: {}", line, e));
+        }
+    }
+}
+
+/// Splits a line of a CSV file into fields. Fields can be wrapped in double
+/// quotes so they can contain commas, with "" standing for a quote inside one.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            ch => field.push(ch),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Finds the index of a column given as a header name or a 1-based number.
+fn find_column(headers: &[String], column: &str) -> Result<usize, ImportError> {
+    let found = match column.parse::<usize>() {
+        Ok(number) if number >= 1 && number <= headers.len() => Some(number - 1),
+        Ok(_) => None,
+        Err(_) => headers
+            .iter()
+            .position(|header| header.trim().eq_ignore_ascii_case(column.trim())),
+    };
+    found.ok_or_else(|| ImportError::MissingColumn(column.to_string()))
+}
+
+impl Bills {
+    /// Imports every row of a bank statement as a payment. Payees are matched
+    /// to bills by name, and payees that don't match any bill get a new bill
+    /// which the row then pays. Rows that can't be imported are skipped and
+    /// listed in the report.
+    fn import_statement(
+        &mut self,
+        contents: &str,
+        options: &ImportOptions,
+    ) -> Result<ImportReport, ImportError> {
+        // NOTE Some banks put a byte order mark in front of the header
+        let mut lines = contents.trim_start_matches('\u{feff}').lines();
+        let headers = match lines.next() {
+            Some(header) => split_csv_line(header),
+            None => return Err(ImportError::EmptyStatement),
+        };
+        let columns = [
+            find_column(&headers, &options.date_column)?,
+            find_column(&headers, &options.payee_column)?,
+            find_column(&headers, &options.amount_column)?,
+        ];
+
+        let mut report = ImportReport::default();
+        for (num, line) in lines.enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields = split_csv_line(line);
+            match self.import_row(&fields, columns, options) {
+                Ok(Some(name)) => report.created.push(name),
+                Ok(None) => report.paid += 1,
+                // NOTE The header is line 1, so the first row is line 2
+                Err(e) => report.skipped.push((num + 2, e)),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Imports a single statement row. Returns the name of the new bill if
+    /// one had to be created.
+    fn import_row(
+        &mut self,
+        fields: &[String],
+        [date_column, payee_column, amount_column]: [usize; 3],
+        options: &ImportOptions,
+    ) -> Result<Option<String>, ImportError> {
+        let field = |index: usize, name: &str| match fields.get(index).map(|field| field.trim()) {
+            Some(field) if !field.is_empty() => Ok(field),
+            _ => Err(ImportError::MissingField(name.to_string())),
         };
 
-        // Already have return None (above), so should be able to just return
-        // the Some() variant for the entire new() fn.
-        Some(Self { name, amount })
+        let date = field(date_column, "date")?;
+        let date = NaiveDate::parse_from_str(date, &options.date_format).map_err(|_| {
+            ImportError::InvalidDate {
+                date: date.to_string(),
+                format: options.date_format.clone(),
+            }
+        })?;
+        // NOTE Bill names can't contain commas, and bank payees often have extra spaces
+        let payee = field(payee_column, "payee")?
+            .replace(',', " ")
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        // NOTE Banks differ on whether money going out is negative, so the sign is dropped
+        let amount = field(amount_column, "amount")?.replacen('-', "", 1);
+
+        let (mut bill, created) = match self.match_payee(&payee) {
+            Some(bill) => (bill.clone(), false),
+            None => {
+                let amount = Money::parse(&amount)?;
+                let bill = Bill {
+                    id: 0,
+                    name: payee.clone(),
+                    amount,
+                    due: date,
+                    recurrence: options.recurrence,
+                    paid: false,
+                    payments: vec![],
+                    category: options.category.clone(),
+                    split: None,
+                };
+                (bill, true)
+            }
+        };
+        let amount = Money::parse_in(&amount, bill.amount.currency)?;
+        // NOTE Without a payer the payment would be left out of the balances
+        let by = match &options.by {
+            Some(by) => Some(by.clone()),
+            None if bill.split.is_some() => return Err(ImportError::MissingPayer(bill.name)),
+            None => None,
+        };
+        bill.pay(date, amount, by)
+            .map_err(|error| ImportError::Payment {
+                name: bill.name.clone(),
+                error,
+            })?;
+
+        if created {
+            self.add_bill(bill);
+            Ok(Some(payee))
+        } else {
+            self.set_bill(bill.id, Some(bill));
+            Ok(None)
+        }
     }
 
-    fn get_bill_amount() -> Option<f64> {
-        // Method 1: Works but panics:
-        // let amount = get_user_input();
-        // let amount: f64 = amount
-        //     .parse()
-        //     .expect("Failed to convert amount from String to f64");
+    /// Finds the bill a bank statement payee is for: the bill with exactly
+    /// that name, otherwise the bill with the longest name that appears in
+    /// the payee, e.g. "netflix" for "NETFLIX.COM 866-579-7172".
+    fn match_payee(&self, payee: &str) -> Option<&Bill> {
+        if let Some(bill) = self.find(payee) {
+            return Some(bill);
+        }
+        let payee = payee.to_lowercase();
+        self.inner
+            .values()
+            .filter(|bill| payee.contains(&bill.name.to_lowercase()))
+            .max_by_key(|bill| (bill.name.len(), std::cmp::Reverse(bill.id)))
+    }
+}
 
-        // Method 2: Handle the error from parse() instead of using expect()
-        // Otherwise it panics and breaks the program
-        // Q: How to handle parse Err variant without panicking?
-        // The Err(_) variant can't just return a print because it expects f64
-        // A: The trick is to use Result<f64, ParseFloatError> when using parse()!
-        // Put it inside a loop
-        // TODO Could turn this into its own function get_bill_amount()
-        loop {
-            println!("Enter bill amount:");
-            let amount = match get_user_input() {
-                Some(amount) => amount,
-                None => return None,
+/// Parses a YYYY-MM-DD date given on the command line.
+fn parse_date(date: &str) -> Result<NaiveDate, chrono::ParseError> {
+    NaiveDate::parse_from_str(date, DATE_FORMAT)
+}
+
+/// Parses a recurrence given on the command line.
+fn parse_recurrence(recurrence: &str) -> Result<Recurrence, String> {
+    Recurrence::from_input(&recurrence.to_lowercase())
+        .ok_or_else(|| "expected once, weekly, monthly or yearly".to_string())
+}
+
+// NOTE Same StructOpt setup as p2: one struct for the options shared by every
+// command, and one enum for the commands themselves
+#[derive(StructOpt, Debug)]
+#[structopt(name = "bills", about = "project 1: bill manager")]
+struct Opt {
+    /// File the bills are loaded from and saved to
+    #[structopt(short = "f", long, parse(from_os_str), default_value = DEFAULT_DATA_FILE)]
+    data_file: PathBuf,
+
+    /// Ledger the command works on
+    #[structopt(short = "l", long, default_value = MAIN_LEDGER)]
+    ledger: String,
+
+    /// Leave out to use the interactive menu
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Add a bill. Names are compared ignoring case, and adding a bill with
+    /// the same name as an existing one fails unless --merge or --replace is given
+    Add {
+        name: String,
+        /// e.g. 12.34, $1,234.56 or "20 EUR"
+        amount: String,
+        /// Due date as YYYY-MM-DD, defaults to today
+        #[structopt(long, parse(try_from_str = parse_date))]
+        due: Option<NaiveDate>,
+        /// once, weekly, monthly or yearly
+        #[structopt(long, parse(try_from_str = parse_recurrence), default_value = "once")]
+        recurrence: Recurrence,
+        /// rent, utilities, subscriptions, or your own
+        #[structopt(long, default_value = "other")]
+        category: String,
+        /// Update the existing bill with the same name, keeping its payments
+        #[structopt(long, conflicts_with = "replace")]
+        merge: bool,
+        /// Replace the existing bill with the same name, dropping its payments
+        #[structopt(long)]
+        replace: bool,
+    },
+    /// List all bills
+    List {},
+    /// Change or rename a bill
+    Edit {
+        name: String,
+        /// New amount, in the bill's currency unless another one is given
+        amount: Option<String>,
+        /// New name for the bill
+        #[structopt(long)]
+        rename: Option<String>,
+        /// New due date as YYYY-MM-DD
+        #[structopt(long, parse(try_from_str = parse_date))]
+        due: Option<NaiveDate>,
+        /// once, weekly, monthly or yearly
+        #[structopt(long, parse(try_from_str = parse_recurrence))]
+        recurrence: Option<Recurrence>,
+        /// rent, utilities, subscriptions, or your own
+        #[structopt(long)]
+        category: Option<String>,
+    },
+    /// Remove a bill
+    Remove { name: String },
+    /// Record a payment on a bill
+    Pay {
+        name: String,
+        /// Amount paid, or "full" to pay everything that's remaining
+        amount: String,
+        /// Date paid as YYYY-MM-DD, defaults to today
+        #[structopt(long, parse(try_from_str = parse_date))]
+        date: Option<NaiveDate>,
+        /// Member who paid, needed when the bill is split between members
+        #[structopt(long)]
+        by: Option<String>,
+    },
+    /// Set the monthly budget for a category
+    Budget { category: String, amount: String },
+    /// Show spending by category against the budgets
+    Summary {
+        /// Month as YYYY-MM, defaults to this month
+        #[structopt(parse(try_from_str = parse_month))]
+        month: Option<NaiveDate>,
+    },
+    /// Show who owes whom for the bills split between members
+    Settle {},
+    /// List, create or delete ledgers
+    Ledger(LedgerCommand),
+    /// Import payments from a bank statement CSV file. Payees are matched to
+    /// bills by name, and a new bill is added for every payee that doesn't match
+    Import {
+        #[structopt(parse(from_os_str))]
+        statement: PathBuf,
+        /// Header name or 1-based number of the date column
+        #[structopt(long, default_value = "date")]
+        date_column: String,
+        /// Header name or 1-based number of the payee column
+        #[structopt(long, default_value = "payee")]
+        payee_column: String,
+        /// Header name or 1-based number of the amount column
+        #[structopt(long, default_value = "amount")]
+        amount_column: String,
+        /// Format of the dates in the statement, e.g. %m/%d/%Y
+        #[structopt(long, default_value = DATE_FORMAT)]
+        date_format: String,
+        /// How often new bills are due: once, weekly, monthly or yearly
+        #[structopt(long, parse(try_from_str = parse_recurrence), default_value = "once")]
+        recurrence: Recurrence,
+        /// Category of new bills
+        #[structopt(long, default_value = "other")]
+        category: String,
+        /// Member who made the payments, needed for bills split between members
+        #[structopt(long)]
+        by: Option<String>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum LedgerCommand {
+    /// List every ledger with its totals
+    List {},
+    /// Create an empty ledger
+    New { name: String },
+    /// Delete a ledger and its file
+    Delete { name: String },
+}
+
+/// Errors that may occur while running a command.
+#[derive(Error, Debug)]
+enum CommandError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("invalid amount: {0}")]
+    InvalidAmount(#[from] MoneyError),
+    #[error("unable to pay: {0}")]
+    Payment(#[from] PaymentError),
+    #[error("bill names cannot be empty")]
+    EmptyName,
+    #[error("bill names cannot contain commas")]
+    InvalidName,
+    #[error("categories cannot contain commas")]
+    InvalidCategory,
+    #[error("bill not found: {0}")]
+    NotFound(String),
+    #[error("a bill named {0} already exists (use --merge or --replace to add it anyway)")]
+    Duplicate(String),
+    #[error("a bill named {0} already exists")]
+    NameTaken(String),
+    #[error("unable to import statement: {0}")]
+    Import(#[from] ImportError),
+    #[error(transparent)]
+    Household(#[from] HouseholdError),
+    #[error("{0} is split between members, use --by to say who paid")]
+    MissingPayer(String),
+    #[error(transparent)]
+    Ledger(#[from] LedgerError),
+}
+
+/// Checks a bill name given on the command line.
+fn parse_name(name: &str) -> Result<String, CommandError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(CommandError::EmptyName);
+    }
+    if name.contains(',') {
+        return Err(CommandError::InvalidName);
+    }
+    Ok(name.to_string())
+}
+
+/// Parses a category given on the command line.
+fn parse_category(category: &str) -> Result<Category, CommandError> {
+    let category = category.trim().to_lowercase();
+    if category.is_empty() || category.contains(',') {
+        return Err(CommandError::InvalidCategory);
+    }
+    Ok(Category::from_input(&category))
+}
+
+/// Runs a single command against a ledger. This is so we can utilize
+/// the question mark operator.
+fn run(
+    data_file: &Path,
+    ledger: &str,
+    cmd: Command,
+    console: &mut dyn Console,
+) -> Result<(), CommandError> {
+    let mut ledgers = Ledgers::load(data_file, console)?;
+    ledgers.switch(ledger)?;
+    let ledger = ledgers.current_mut();
+    let bills = &mut ledger.bills;
+
+    match cmd {
+        Command::Add {
+            name,
+            amount,
+            due,
+            recurrence,
+            category,
+            merge,
+            replace,
+        } => {
+            // NOTE Names keep their case, bills are found ignoring case instead
+            let name = parse_name(&name)?;
+            let category = parse_category(&category)?;
+            let bill = Bill {
+                id: 0,
+                name,
+                amount: Money::parse(&amount)?,
+                due: due.unwrap_or_else(today),
+                recurrence,
+                paid: false,
+                payments: vec![],
+                category,
+                split: None,
             };
-            // BROKEN:
-            // let amount: Result<f64, _> = match amount.parse() {
-            //     Ok(inner_amount) => {
-            //         let amount: f64 = inner_amount;
-            //         return Self { name, amount};
-            //     },
-            //     Err(_) => println!("Please enter a valid number")
-            // };
-            // WORKS:
-            // NOTE Trick is to return Result<f64, _> instead of f64
-            let parsed_amount: Result<f64, _> =  amount.parse();
-            match parsed_amount {
-                Ok(inner_amount) => {
-                    // NOTE MUST 'return' from inside loop, otherwise infinite!
-                    return Some(inner_amount);
-                },
-                Err(_) => {
-                    println!("Please enter a valid number");
-                    return None;
-                },
+            match bills.find(&bill.name).cloned() {
+                None => {
+                    bills.add_bill(bill);
+                }
+                Some(existing) if merge => bills.set_bill(existing.id, Some(existing.merged(bill))),
+                Some(existing) if replace => {
+                    bills.set_bill(existing.id, Some(existing.replaced(bill)))
+                }
+                Some(existing) => return Err(CommandError::Duplicate(existing.name)),
             }
         }
-
+        Command::List {} => {
+            bills.view_bills(console);
+            return Ok(());
+        }
+        Command::Edit {
+            name,
+            amount,
+            rename,
+            due,
+            recurrence,
+            category,
+        } => {
+            let mut bill = bills
+                .find(&name)
+                .cloned()
+                .ok_or_else(|| CommandError::NotFound(name.clone()))?;
+            if let Some(amount) = amount {
+                bill.amount = Money::parse_in(&amount, bill.amount.currency)?;
+            }
+            if let Some(rename) = rename {
+                let rename = parse_name(&rename)?;
+                // NOTE Changing just the case of the name is fine, it's still the same bill
+                if let Some(other) = bills.find(&rename).filter(|other| other.id != bill.id) {
+                    return Err(CommandError::NameTaken(other.name.clone()));
+                }
+                bill.name = rename;
+            }
+            if let Some(due) = due {
+                bill.due = due;
+            }
+            if let Some(recurrence) = recurrence {
+                bill.recurrence = recurrence;
+            }
+            if let Some(category) = category {
+                bill.category = parse_category(&category)?;
+            }
+            bills.set_bill(bill.id, Some(bill));
+        }
+        Command::Remove { name } => {
+            bills.remove(&name).ok_or(CommandError::NotFound(name))?;
+        }
+        Command::Pay {
+            name,
+            amount,
+            date,
+            by,
+        } => {
+            let mut bill = bills
+                .find(&name)
+                .cloned()
+                .ok_or_else(|| CommandError::NotFound(name.clone()))?;
+            let amount = if amount.to_lowercase() == "full" {
+                bill.remaining()
+            } else {
+                Money::parse_in(&amount, bill.amount.currency)?
+            };
+            let by = match by {
+                Some(by) => Some(
+                    bills
+                        .find_member(&by)
+                        .cloned()
+                        .ok_or(HouseholdError::UnknownMember(by))?,
+                ),
+                None if bill.split.is_some() => return Err(CommandError::MissingPayer(bill.name)),
+                None => None,
+            };
+            bill.pay(date.unwrap_or_else(today), amount, by)?;
+            bills.set_bill(bill.id, Some(bill));
+        }
+        Command::Budget { category, amount } => {
+            let category = parse_category(&category)?;
+            bills.set_budget(category, Some(Money::parse(&amount)?));
+        }
+        Command::Summary { month } => {
+            bills.summary(month.unwrap_or_else(|| month_start(today())), console);
+            return Ok(());
+        }
+        Command::Settle {} => {
+            bills.settle(console);
+            return Ok(());
+        }
+        Command::Import {
+            statement,
+            date_column,
+            payee_column,
+            amount_column,
+            date_format,
+            recurrence,
+            category,
+            by,
+        } => {
+            let by = match by {
+                Some(by) => Some(
+                    bills
+                        .find_member(&by)
+                        .cloned()
+                        .ok_or(HouseholdError::UnknownMember(by))?,
+                ),
+                None => None,
+            };
+            let options = ImportOptions {
+                date_column,
+                payee_column,
+                amount_column,
+                date_format,
+                recurrence,
+                category: parse_category(&category)?,
+                by,
+            };
+            let contents = fs::read_to_string(statement)?;
+            bills.import_statement(&contents, &options)?.print(console);
+        }
+        Command::Ledger(command) => {
+            match command {
+                LedgerCommand::List {} => ledgers.totals(console),
+                LedgerCommand::New { name } => ledgers.create(&name, console)?,
+                LedgerCommand::Delete { name } => {
+                    ledgers.delete(&name)?;
+                }
+            }
+            return Ok(());
+        }
     }
+
+    save_bills(&ledger.file, bills)?;
+    Ok(())
 }
 
-fn main() {
-    let mut bills = Bills::new();
+/// Runs the interactive menu until the user quits.
+fn run_menu(data_file: &Path, console: &mut dyn Console) -> io::Result<()> {
+    let mut ledgers = Ledgers::load(data_file, console)?;
 
     loop {
-        MenuOption::show_menu();
-        let user_option: Option<MenuOption> = MenuOption::get_user_option();
+        MenuOption::show_menu(&ledgers.current, console);
+        let user_option: Option<MenuOption> = MenuOption::get_user_option(console);
 
         match user_option {
-            Some(option) => MenuOption::process_option(option, &mut bills),
+            Some(option) => MenuOption::process_option(option, &mut ledgers, console),
             None => {
-                println!("Invalid option");
+                console.print("Invalid option");
                 break;
             }
         }
     }
+
+    let ledger = ledgers.current();
+    save_or_report(&ledger.file, &ledger.bills, console);
+    Ok(())
+}
+
+fn main() {
+    // NOTE Without a command the interactive menu runs, e.g.:
+    // cargo run --bin p1 -- -f my_bills.csv
+    // cargo run --bin p1 -- -f my_bills.csv add rent 1200 --due 2021-11-01 --recurrence monthly
+    // cargo run --bin p1 -- -f my_bills.csv --ledger office list
+    let opt = Opt::from_args();
+    match opt.cmd {
+        Some(cmd) => {
+            if let Err(e) = run(&opt.data_file, &opt.ledger, cmd, &mut Terminal) {
+                eprintln!("an error occurred: {}", e);
+                std::process::exit(1);
+            }
+        }
+        None => {
+            if let Err(e) = run_menu(&opt.data_file, &mut Terminal) {
+                eprintln!("an error occurred: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use std::collections::VecDeque;
+
+    /// Feeds the menus a script of inputs and records everything they print.
+    struct Script {
+        inputs: VecDeque<String>,
+        /// Every line printed, with the inputs shown as "> input".
+        transcript: Vec<String>,
+    }
+
+    impl Script {
+        fn new(inputs: &[&str]) -> Self {
+            Self {
+                inputs: inputs.iter().map(|input| input.to_string()).collect(),
+                transcript: vec![],
+            }
+        }
+
+        fn contains(&self, line: &str) -> bool {
+            self.transcript
+                .iter()
+                .any(|printed| printed.trim_end() == line)
+        }
+    }
+
+    impl Console for Script {
+        fn read_line(&mut self) -> String {
+            // NOTE Running out of inputs acts like the user pressing enter, which
+            // backs out of whatever menu is open
+            let input = self.inputs.pop_front().unwrap_or_default();
+            self.transcript.push(format!("> {}", input));
+            input
+        }
+
+        fn print(&mut self, line: &str) {
+            self.transcript
+                .extend(line.lines().map(|line| line.to_string()));
+        }
+    }
+
+    /// Runs the menu with a script against a fresh data file, returning the
+    /// transcript and the bills that were saved.
+    fn run_script(name: &str, inputs: &[&str]) -> (Script, Bills) {
+        let data_file = std::env::temp_dir().join(format!("p1_test_{}.csv", name));
+        let _ = fs::remove_file(&data_file);
+        let mut script = Script::new(inputs);
+        run_menu(&data_file, &mut script).unwrap();
+        let (bills, _) = load_bills(&data_file, &mut Script::new(&[])).unwrap();
+        let _ = fs::remove_file(&data_file);
+        (script, bills)
+    }
+
+    fn usd(cents: i64) -> Money {
+        Money {
+            cents,
+            currency: Currency(*b"USD"),
+        }
+    }
+
+    /// A monthly bill in the "other" category, first due on 2021-10-01.
+    /// Tests that need other details change them with `..bill(name, amount)`.
+    fn bill(name: &str, amount: Money) -> Bill {
+        Bill {
+            id: 0,
+            name: name.to_string(),
+            amount,
+            due: NaiveDate::from_ymd_opt(2021, 10, 1).unwrap(),
+            recurrence: Recurrence::Monthly,
+            paid: false,
+            payments: vec![],
+            category: Category::other(),
+            split: None,
+        }
+    }
+
+    #[test]
+    fn check_money_parse() {
+        assert_eq!(Money::parse("12"), Ok(usd(1200)));
+        assert_eq!(Money::parse("0.1"), Ok(usd(10)));
+        assert_eq!(Money::parse("0.05"), Ok(usd(5)));
+        assert_eq!(Money::parse("$1,234.56"), Ok(usd(123456)));
+        assert_eq!(Money::parse("1234.56 usd"), Ok(usd(123456)));
+        assert_eq!(
+            Money::parse("€20"),
+            Ok(Money {
+                cents: 2000,
+                currency: Currency(*b"EUR")
+            })
+        );
+    }
+
+    #[test]
+    fn check_money_parse_rejects() {
+        assert_eq!(Money::parse("1.234"), Err(MoneyError::TooManyDecimals));
+        assert_eq!(Money::parse("-5"), Err(MoneyError::Negative));
+        assert_eq!(Money::parse("$-5"), Err(MoneyError::Negative));
+        assert!(matches!(
+            Money::parse("12,34"),
+            Err(MoneyError::InvalidNumber(_))
+        ));
+        assert!(matches!(
+            Money::parse("abc"),
+            Err(MoneyError::InvalidNumber(_))
+        ));
+        assert!(matches!(
+            Money::parse("$5 eur"),
+            Err(MoneyError::MismatchedCurrency(_))
+        ));
+        assert_eq!(Money::parse("1000000000000"), Err(MoneyError::TooLarge));
+    }
+
+    #[test]
+    fn check_totals_are_exact() {
+        let mut bills = Bills::new();
+        for (name, amount) in [("a", "0.10"), ("b", "0.20"), ("c", "5 gbp")] {
+            bills.add_bill(bill(name, Money::parse(amount).unwrap()));
+        }
+        let totals: Vec<String> = bills.totals().iter().map(|t| t.to_string()).collect();
+        assert_eq!(totals, vec!["5.00 GBP", "0.30 USD"]);
+    }
+
+    #[test]
+    fn check_due_bills() {
+        let mut bills = Bills::new();
+        let today = NaiveDate::from_ymd_opt(2021, 10, 10).unwrap();
+        for (name, day) in [
+            ("phone", 12),
+            ("rent", 1),
+            ("gym", 10),
+            ("water", 8),
+            ("tax", 20),
+        ] {
+            bills.add_bill(Bill {
+                due: NaiveDate::from_ymd_opt(2021, 10, day).unwrap(),
+                ..bill(name, usd(1000))
+            });
+        }
+        // Paid bills are never due
+        bills.add_bill(Bill {
+            due: NaiveDate::from_ymd_opt(2021, 10, 2).unwrap(),
+            paid: true,
+            ..bill("insurance", usd(1000))
+        });
+
+        let mut script = Script::new(&[]);
+        bills.due_bills(today, 7, &mut script);
+        #[rustfmt::skip]
+        let expected = [
+            "",
+            "** Overdue **",
+            "rent - 10.00 USD - due 2021-10-01 (9 day(s) late)",
+            "water - 10.00 USD - due 2021-10-08 (2 day(s) late)",
+            "",
+            "** Due in the next 7 day(s) **",
+            "gym - 10.00 USD - due 2021-10-10 (in 0 day(s))",
+            "phone - 10.00 USD - due 2021-10-12 (in 2 day(s))",
+        ];
+        assert_eq!(script.transcript, expected);
+
+        let mut script = Script::new(&[]);
+        bills.due_bills(NaiveDate::from_ymd_opt(2021, 9, 1).unwrap(), 0, &mut script);
+        assert!(script.contains("Nothing overdue"));
+        assert!(script.contains("Nothing due"));
+    }
+
+    #[test]
+    fn check_summary() {
+        let mut bills = Bills::new();
+        bills.add_bill(Bill {
+            category: Category::Utilities,
+            ..bill("water", usd(3000))
+        });
+        bills.add_bill(Bill {
+            category: Category::Rent,
+            ..bill("rent", usd(120000))
+        });
+        bills.add_bill(Bill {
+            category: Category::Custom("gym".to_string()),
+            ..bill("gym", usd(2500))
+        });
+        bills.add_bill(Bill {
+            category: Category::Subscriptions,
+            ..bill("music", Money::parse("10 EUR").unwrap())
+        });
+        bills.set_budget(Category::Rent, Some(usd(100000)));
+        bills.set_budget(Category::Utilities, Some(usd(5000)));
+        bills.set_budget(Category::Subscriptions, Some(usd(2000)));
+
+        let mut script = Script::new(&[]);
+        let october = NaiveDate::from_ymd_opt(2021, 10, 1).unwrap();
+        bills.summary(october, &mut script);
+        // Categories are sorted by name, and only rent is over its budget
+        #[rustfmt::skip]
+        let expected = [
+            "",
+            "** Summary for 2021-10 **",
+            "category          spending       budget  status",
+            "-------------  -----------  -----------  ------------------",
+            "gym              25.00 USD            -  no budget",
+            "rent           1200.00 USD  1000.00 USD  OVER by 200.00 USD",
+            "subscriptions    10.00 EUR    20.00 USD  budget is in USD",
+            "subscriptions     0.00 USD    20.00 USD  ok",
+            "utilities        30.00 USD    50.00 USD  ok",
+        ];
+        assert_eq!(script.transcript, expected);
+    }
+
+    #[test]
+    fn check_times_due_in_month() {
+        let october = NaiveDate::from_ymd_opt(2021, 10, 1).unwrap();
+        let mut bill = Bill {
+            // A Tuesday, so the bill also falls on the 5th, 12th, 19th and 26th
+            due: NaiveDate::from_ymd_opt(2021, 11, 2).unwrap(),
+            recurrence: Recurrence::Weekly,
+            ..bill("gym", usd(1000))
+        };
+        let november = add_months(october, 1);
+        // Nothing is due before the first due date, then every Tuesday from the 2nd
+        assert_eq!(bill.times_due_in_month(october), 0);
+        assert_eq!(bill.times_due_in_month(november), 5);
+        assert_eq!(bill.times_due_in_month(add_months(october, 2)), 4);
+
+        bill.recurrence = Recurrence::Monthly;
+        assert_eq!(bill.times_due_in_month(october), 0);
+        assert_eq!(bill.times_due_in_month(november), 1);
+        assert_eq!(bill.times_due_in_month(add_months(october, 5)), 1);
+
+        // Yearly bills are only due in the month of their first due date
+        bill.recurrence = Recurrence::Yearly;
+        assert_eq!(bill.times_due_in_month(october), 0);
+        assert_eq!(bill.times_due_in_month(november), 1);
+        assert_eq!(bill.times_due_in_month(add_months(october, 2)), 0);
+        assert_eq!(bill.times_due_in_month(add_months(october, 13)), 1);
+
+        bill.recurrence = Recurrence::Once;
+        assert_eq!(bill.times_due_in_month(november), 1);
+        assert_eq!(bill.times_due_in_month(add_months(october, 2)), 0);
+
+        // Paying a recurring bill moves its due date on, but its payments
+        // still count from the first due date
+        bill.recurrence = Recurrence::Monthly;
+        bill.pay(
+            NaiveDate::from_ymd_opt(2021, 11, 2).unwrap(),
+            usd(1000),
+            None,
+        )
+        .unwrap();
+        assert_eq!(bill.due, NaiveDate::from_ymd_opt(2021, 12, 2).unwrap());
+        assert_eq!(bill.times_due_in_month(november), 1);
+        assert_eq!(bill.times_due_in_month(october), 0);
+    }
+
+    #[test]
+    fn check_undo_redo() {
+        let mut bills = Bills::new();
+        let bill = Bill {
+            id: 1,
+            category: Category::Rent,
+            ..bill("rent", usd(10000))
+        };
+        bills.add_bill(bill.clone());
+        bills.set_bill(
+            1,
+            Some(Bill {
+                amount: usd(12000),
+                ..bill.clone()
+            }),
+        );
+        bills.set_budget(Category::Rent, Some(usd(11000)));
+
+        assert_eq!(bills.undo(), Some("budget for rent".to_string()));
+        assert!(bills.budgets.is_empty());
+        assert_eq!(bills.undo(), Some("edit rent".to_string()));
+        assert_eq!(bills.inner.get(&1), Some(&bill));
+        assert_eq!(bills.undo(), Some("add rent".to_string()));
+        assert!(bills.inner.is_empty());
+        assert_eq!(bills.undo(), None);
+
+        assert_eq!(bills.redo(), Some("add rent".to_string()));
+        assert_eq!(bills.inner.get(&1), Some(&bill));
+
+        // A new change clears whatever was left to redo
+        bills.remove("rent");
+        assert_eq!(bills.redo(), None);
+        assert!(bills.inner.is_empty());
+    }
+
+    #[test]
+    fn check_partial_payments_roll_bill_forward() {
+        let due = NaiveDate::from_ymd_opt(2021, 1, 31).unwrap();
+        let mut bill = Bill {
+            due,
+            category: Category::Rent,
+            ..bill("rent", usd(10000))
+        };
+
+        assert_eq!(bill.pay(due, usd(4000), None), Ok(()));
+        assert_eq!(bill.remaining(), usd(6000));
+        assert_eq!(
+            bill.pay(due, usd(7000), None),
+            Err(PaymentError::Overpaid(usd(6000)))
+        );
+        assert_eq!(bill.pay(due, usd(6000), None), Ok(()));
+
+        assert_eq!(bill.due, NaiveDate::from_ymd_opt(2021, 2, 28).unwrap());
+        assert_eq!(bill.remaining(), usd(10000));
+        assert_eq!(bill.payments.len(), 2);
+    }
+
+    #[test]
+    fn check_rename_keeps_id_and_payments() {
+        let mut bills = Bills::new();
+        let mut bill = Bill {
+            category: Category::Subscriptions,
+            ..bill("Netflix", usd(1500))
+        };
+        bill.pay(bill.due, usd(1500), None).unwrap();
+        let id = bills.add_bill(bill);
+
+        assert_eq!(bills.find("NETFLIX").map(|bill| bill.id), Some(id));
+        let mut renamed = bills.find("netflix").cloned().unwrap();
+        renamed.name = "Streaming".to_string();
+        bills.set_bill(id, Some(renamed));
+
+        assert!(bills.find("netflix").is_none());
+        let bill = bills.find("streaming").unwrap();
+        assert_eq!(bill.id, id);
+        assert_eq!(bill.payments.len(), 1);
+        assert_eq!(
+            bills.undo(),
+            Some("rename Netflix to Streaming".to_string())
+        );
+        assert_eq!(bills.inner[&id].name, "Netflix");
+    }
+
+    #[test]
+    fn check_merge_and_replace_keep_id() {
+        let due = NaiveDate::from_ymd_opt(2021, 10, 1).unwrap();
+        let mut existing = Bill {
+            id: 7,
+            category: Category::Rent,
+            ..bill("Rent", usd(100000))
+        };
+        existing.pay(due, usd(100000), None).unwrap();
+        let new = Bill {
+            category: Category::Rent,
+            ..bill("rent", usd(120000))
+        };
+
+        let merged = existing.merged(new.clone());
+        assert_eq!((merged.id, merged.name.as_str()), (7, "Rent"));
+        assert_eq!(merged.amount, usd(120000));
+        assert_eq!(merged.payments.len(), 1);
+
+        let replaced = existing.replaced(new);
+        assert_eq!((replaced.id, replaced.name.as_str()), (7, "rent"));
+        assert!(replaced.payments.is_empty());
+    }
+
+    #[test]
+    fn check_old_files_get_ids() {
+        let contents = "name,amount,due,recurrence,paid,currency,payments,category\n\
+                        water,30.00,2021-10-01,monthly,false,USD,,utilities\n\
+                        rent,1000.00,2021-10-01,monthly,false,USD,,rent,4\n"
+            .to_string();
+        let (bills, skipped) = parse_bills(contents, &mut Script::new(&[]));
+        assert_eq!(skipped, 0);
+        assert_eq!(bills.find("rent").map(|bill| bill.id), Some(4));
+        assert_eq!(bills.find("water").map(|bill| bill.id), Some(5));
+        assert_eq!(bills.next_id, 6);
+    }
+
+    #[test]
+    fn check_menu_add_and_view() {
+        let (script, bills) = run_script(
+            "add_and_view",
+            &[
+                // NOTE An invalid amount cancels the add, so the bill is entered again
+                "add",
+                "Rent",
+                "12,00",
+                "add",
+                "Rent",
+                "1200",
+                "2021-10-01",
+                "monthly",
+                "rent",
+                "view",
+            ],
+        );
+        assert!(script.contains("Please enter a valid amount: not a valid amount: 12,00"));
+        assert!(script.contains("Rent  rent      1200.00 USD  1200.00 USD  2021-10-01  monthly"));
+        assert!(script.contains("total = 1200.00 USD"));
+        assert!(script.contains("Invalid option"));
+
+        let bill = bills.find("rent").unwrap();
+        assert_eq!(bill.name, "Rent");
+        assert_eq!(bill.amount, usd(120000));
+        assert_eq!(bill.category, Category::Rent);
+    }
+
+    #[test]
+    fn check_menu_duplicate_rename_and_undo() {
+        #[rustfmt::skip]
+        let inputs = [
+            "add", "water", "30", "2021-10-01", "monthly", "utilities",
+            "add", "WATER", "35", "2021-10-01", "monthly", "utilities", "maybe", "merge",
+            "edit", "water", "name", "Water & Sewer",
+            "add", "gas", "40", "2021-10-05", "monthly", "utilities",
+            "edit", "gas", "name", "water & sewer", "Gas",
+            "undo",
+        ];
+        let (script, bills) = run_script("duplicate_rename_and_undo", &inputs);
+        assert!(script.contains("A bill named \"water\" already exists:"));
+        assert!(script.contains("Please enter merge, replace or cancel"));
+        assert!(script.contains("There is already a bill named \"Water & Sewer\""));
+        assert!(script.contains("Undid rename gas to Gas"));
+
+        let water = bills.find("WATER & SEWER").unwrap();
+        assert_eq!((water.id, water.amount), (1, usd(3500)));
+        assert_eq!(
+            bills.find("gas").map(|bill| bill.name.as_str()),
+            Some("gas")
+        );
+        assert_eq!(bills.inner.len(), 2);
+    }
+
+    #[test]
+    fn check_menu_pay_and_remove() {
+        #[rustfmt::skip]
+        let inputs = [
+            "add", "phone", "50", "2021-10-01", "once", "utilities",
+            "add", "gym", "20", "2021-10-01", "monthly", "other",
+            "pay", "phone", "2021-10-02", "60", "20",
+            "remove", "GYM",
+            "remove", "gym",
+        ];
+        let (script, bills) = run_script("pay_and_remove", &inputs);
+        assert!(script.contains("payment is more than the 50.00 USD remaining"));
+        assert!(script.contains("30.00 USD still owed on \"phone\""));
+        assert!(script.contains("Removed bill: \"gym\""));
+        assert!(script.contains("Bill not found. Please double-check bill name and try again."));
+
+        let phone = bills.find("phone").unwrap();
+        assert_eq!(phone.remaining(), usd(3000));
+        assert!(bills.find("gym").is_none());
+    }
+
+    #[test]
+    fn check_import_statement() {
+        let mut bills = Bills::new();
+        bills.add_bill(Bill {
+            due: NaiveDate::from_ymd_opt(2021, 10, 5).unwrap(),
+            category: Category::Subscriptions,
+            ..bill("netflix", usd(1500))
+        });
+        let statement = "Posted,Description,Debit\n\
+                         10/04/2021,NETFLIX.COM 866-579-7172,-15.00\n\
+                         10/06/2021,\"City of Lansing, Water\",\"-1,030.20\"\n\
+                         10/07/2021,Corner Store,abc\n\
+                         2021-10-08,Corner Store,4.00\n\
+                         10/09/2021,Gym\n";
+        let options = ImportOptions {
+            date_column: "posted".to_string(),
+            payee_column: "2".to_string(),
+            amount_column: "debit".to_string(),
+            date_format: "%m/%d/%Y".to_string(),
+            recurrence: Recurrence::Once,
+            category: Category::Utilities,
+            by: None,
+        };
+        let report = bills.import_statement(statement, &options).unwrap();
+
+        assert_eq!(report.paid, 1);
+        assert_eq!(report.created, vec!["City of Lansing Water"]);
+        let skipped: Vec<(usize, String)> = report
+            .skipped
+            .iter()
+            .map(|(line, e)| (*line, e.to_string()))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                (4, "invalid amount: not a valid amount: abc".to_string()),
+                (
+                    5,
+                    "date doesn't match the format %m/%d/%Y: 2021-10-08".to_string()
+                ),
+                (6, "missing amount".to_string()),
+            ]
+        );
+
+        let netflix = bills.find("netflix").unwrap();
+        assert_eq!(netflix.due, NaiveDate::from_ymd_opt(2021, 11, 5).unwrap());
+        let water = bills.find("city of lansing water").unwrap();
+        assert_eq!(water.amount, usd(103020));
+        assert!(water.paid);
+
+        // A column that isn't in the statement stops the whole import
+        let options = ImportOptions {
+            date_column: "date".to_string(),
+            payee_column: "payee".to_string(),
+            amount_column: "4".to_string(),
+            date_format: DATE_FORMAT.to_string(),
+            recurrence: Recurrence::Once,
+            category: Category::other(),
+            by: None,
+        };
+        assert!(matches!(
+            bills.import_statement("date,payee,amount\n", &options),
+            Err(ImportError::MissingColumn(column)) if column == "4"
+        ));
+
+        // Payments on split bills need to say who paid, so they count in the balances
+        bills.add_member("ann").unwrap();
+        bills.add_member("bob").unwrap();
+        let mut netflix = bills.find("netflix").unwrap().clone();
+        netflix.split = Some(Split::from_input("equal", "ann, bob", DEFAULT_CURRENCY).unwrap());
+        bills.set_bill(netflix.id, Some(netflix));
+        let statement = "date,payee,amount\n2021-11-04,Netflix,15.00\n";
+        let options = ImportOptions {
+            amount_column: "amount".to_string(),
+            ..options
+        };
+        let report = bills.import_statement(statement, &options).unwrap();
+        assert_eq!(report.paid, 0);
+        assert_eq!(
+            report.skipped[0].1.to_string(),
+            "netflix is split between members, use --by to say who paid"
+        );
+        let options = ImportOptions {
+            by: Some("ann".to_string()),
+            ..options
+        };
+        let report = bills.import_statement(statement, &options).unwrap();
+        assert_eq!(report.paid, 1);
+        let netflix = bills.find("netflix").unwrap();
+        assert_eq!(netflix.payments.last().unwrap().by.as_deref(), Some("ann"));
+    }
+
+    #[test]
+    fn check_run_commands() {
+        let data_file = std::env::temp_dir().join("p1_test_commands.csv");
+        let _ = fs::remove_file(&data_file);
+        let data_path = data_file.to_str().unwrap().to_string();
+        let run_args = |args: &[&str], script: &mut Script| {
+            let mut all_args = vec!["p1", "-f", &data_path];
+            all_args.extend_from_slice(args);
+            let opt = Opt::from_iter(all_args);
+            run(&opt.data_file, &opt.ledger, opt.cmd.unwrap(), script)
+        };
+        let mut script = Script::new(&[]);
+
+        #[rustfmt::skip]
+        let commands: [&[&str]; 3] = [
+            &["add", "Rent", "1200", "--due", "2021-10-01", "--recurrence", "monthly", "--category", "rent"],
+            &["pay", "rent", "1200", "--date", "2021-09-30"],
+            &["edit", "RENT", "1250", "--rename", "Flat rent"],
+        ];
+        for args in commands.iter() {
+            run_args(args, &mut script).unwrap();
+        }
+        run_args(&["list"], &mut script).unwrap();
+        // Paying in full rolled the bill forward, and the edit kept the payment
+        assert!(
+            script.contains("Flat rent  rent      1250.00 USD  1250.00 USD  2021-11-01  monthly")
+        );
+        assert_eq!(
+            fs::read_to_string(&data_file).unwrap(),
+            "name,amount,due,recurrence,paid,currency,payments,category,id,split\n\
+             Flat rent,1250.00,2021-11-01,monthly,false,USD,2021-09-30:1200.00:2021-10-01,rent,1,\n"
+        );
+
+        let err = |args: &[&str]| {
+            run_args(args, &mut Script::new(&[]))
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(err(&["add", " ", "5"]), "bill names cannot be empty");
+        assert_eq!(
+            err(&["add", "a,b", "5"]),
+            "bill names cannot contain commas"
+        );
+        assert_eq!(
+            err(&["add", "flat rent", "5"]),
+            "a bill named Flat rent already exists (use --merge or --replace to add it anyway)"
+        );
+        assert_eq!(err(&["pay", "gym", "5"]), "bill not found: gym");
+        assert_eq!(err(&["edit", "gym", "5"]), "bill not found: gym");
+        // Nothing is saved when a command fails
+        assert!(fs::read_to_string(&data_file)
+            .unwrap()
+            .ends_with("rent,1,\n"));
+        let _ = fs::remove_file(&data_file);
+    }
+
+    #[test]
+    fn check_unreadable_file_is_kept() {
+        let data_file = std::env::temp_dir().join("p1_test_unreadable.csv");
+        // NOTE 0xff can never appear in UTF-8, so the file can't be read as text
+        let contents = b"name,amount\nrent,1000.00\nwater,30\xff\n".to_vec();
+        fs::write(&data_file, &contents).unwrap();
+
+        let mut script = Script::new(&["add", "gas", "20", "2021-10-01", "once", "other"]);
+        assert!(run_menu(&data_file, &mut script).is_err());
+        let cmd = Command::Remove {
+            name: "rent".to_string(),
+        };
+        assert!(run(&data_file, MAIN_LEDGER, cmd, &mut Script::new(&[])).is_err());
+        assert_eq!(fs::read(&data_file).unwrap(), contents);
+        let _ = fs::remove_file(&data_file);
+    }
+
+    #[test]
+    fn check_menu_ledgers() {
+        let dir = std::env::temp_dir().join("p1_test_ledgers");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let data_file = dir.join("bills.csv");
+
+        #[rustfmt::skip]
+        let inputs = [
+            "ledger", "new Home Office",
+            "ledger", "new main",
+            "ledger", "new office",
+            "add", "printer", "5 gbp", "2021-10-01", "once", "other",
+            "ledger", "switch main",
+            "add", "rent", "1000", "2021-10-01", "monthly", "rent",
+            "ledger", "new side-gig",
+            "add", "domain", "12", "2021-10-01", "yearly", "other",
+            "ledger", "delete side-gig",
+            "ledger", "switch main",
+            "ledger", "delete side-gig", "yes",
+            "totals",
+        ];
+        let mut script = Script::new(&inputs);
+        run_menu(&data_file, &mut script).unwrap();
+        assert!(script.contains(
+            "ledger names can only have lowercase letters, numbers and dashes: \"home office\""
+        ));
+        assert!(script.contains("main is the name of the main ledger"));
+        assert!(script.contains("Created ledger office, now using it"));
+        assert!(script.contains("the side-gig ledger is in use, switch to another ledger first"));
+        assert!(script.contains("Deleted ledger side-gig"));
+        assert!(script.contains("main        1  1000.00 USD"));
+        assert!(script.contains("office      1  5.00 GBP"));
+        assert!(script.contains("all         2  5.00 GBP, 1000.00 USD"));
+
+        // Only the ledgers listed in the main file are loaded
+        fs::copy(
+            dir.join("bills_office.csv"),
+            dir.join("bills_office-backup.csv"),
+        )
+        .unwrap();
+        let ledgers = Ledgers::load(&data_file, &mut Script::new(&[])).unwrap();
+        let names: Vec<&String> = ledgers.inner.keys().collect();
+        assert_eq!(names, vec!["main", "office"]);
+        assert!(fs::read_to_string(&data_file)
+            .unwrap()
+            .ends_with("\n[ledgers]\noffice\n"));
+        assert_eq!(ledgers.inner["office"].file, dir.join("bills_office.csv"));
+        assert!(ledgers.inner["office"].bills.find("printer").is_some());
+        assert!(!dir.join("bills_side-gig.csv").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn check_split_shares() {
+        let names = |shares: Vec<(&str, Money)>| -> Vec<(String, i64)> {
+            shares
+                .into_iter()
+                .map(|(member, share)| (member.to_string(), share.cents))
+                .collect()
+        };
+        let equal = Split::from_input("equal", "a, b, c", DEFAULT_CURRENCY).unwrap();
+        assert_eq!(
+            names(equal.shares(usd(1000))),
+            vec![("a".into(), 334), ("b".into(), 333), ("c".into(), 333)]
+        );
+
+        let percent = Split::from_input("percent", "a 33.33, b 66.67%", DEFAULT_CURRENCY).unwrap();
+        assert_eq!(percent.check(usd(1000)), Ok(()));
+        assert_eq!(
+            names(percent.shares(usd(1000))),
+            vec![("a".into(), 333), ("b".into(), 667)]
+        );
+
+        // A partial payment on a fixed split is shared in the same proportions
+        let fixed = Split::from_input("fixed", "a 700, b 300", DEFAULT_CURRENCY).unwrap();
+        assert_eq!(fixed.check(usd(100000)), Ok(()));
+        assert_eq!(
+            names(fixed.shares(usd(50000))),
+            vec![("a".into(), 35000), ("b".into(), 15000)]
+        );
+
+        assert_eq!(
+            Split::from_input("percent", "a 60, b 30", DEFAULT_CURRENCY)
+                .unwrap()
+                .check(usd(1000)),
+            Err(HouseholdError::PercentTotal("90".to_string()))
+        );
+        assert_eq!(
+            Split::from_input("equal", "a, A", DEFAULT_CURRENCY)
+                .unwrap()
+                .check(usd(1000)),
+            Err(HouseholdError::DuplicateMember("A".to_string()))
+        );
+        assert_eq!(
+            Split::from_field(&percent.to_field(), DEFAULT_CURRENCY),
+            Ok(percent)
+        );
+    }
+
+    #[test]
+    fn check_members_section() {
+        let data_file = std::env::temp_dir().join("p1_test_members.csv");
+        let mut bills = Bills::new();
+        bills.add_member("member").unwrap();
+        bills.add_member("Sam").unwrap();
+        assert!(matches!(
+            bills.add_member("[members]"),
+            Err(HouseholdError::InvalidMemberName(_))
+        ));
+        save_bills(&data_file, &bills).unwrap();
+        let (loaded, skipped) = load_bills(&data_file, &mut Script::new(&[])).unwrap();
+        let _ = fs::remove_file(&data_file);
+        assert_eq!(skipped, 0);
+        assert_eq!(loaded.members, bills.members);
+
+        // Older files started the section with "member" instead
+        let contents = "name,amount,due,recurrence,paid,currency,payments,category\n\
+                        rent,1000.00,2021-10-01,monthly,false,USD,,rent\n\n\
+                        member\nSam\nmember\n"
+            .to_string();
+        let (loaded, skipped) = parse_bills(contents, &mut Script::new(&[]));
+        assert_eq!(skipped, 0);
+        assert_eq!(loaded.members, bills.members);
+    }
+
+    #[test]
+    fn check_settlement() {
+        let mut bills = Bills::new();
+        for member in ["Alice", "Bob", "Carol"] {
+            bills.add_member(member).unwrap();
+        }
+        assert_eq!(
+            bills.add_member("alice"),
+            Err(HouseholdError::MemberExists("Alice".to_string()))
+        );
+        let due = NaiveDate::from_ymd_opt(2021, 10, 1).unwrap();
+        let split_bill = |name: &str, cents: i64, split: Split| Bill {
+            split: Some(split),
+            ..bill(name, usd(cents))
+        };
+
+        let mut rent = split_bill(
+            "rent",
+            90000,
+            Split::Equal(vec!["Alice".into(), "Bob".into(), "Carol".into()]),
+        );
+        rent.pay(due, usd(90000), Some("Alice".to_string()))
+            .unwrap();
+        bills.add_bill(rent);
+        let mut power = split_bill(
+            "power",
+            9000,
+            Split::Percent(vec![("Alice".into(), 5000), ("Bob".into(), 5000)]),
+        );
+        power.pay(due, usd(9000), Some("Bob".to_string())).unwrap();
+        bills.add_bill(power);
+
+        assert_eq!(
+            bills.balances()[&DEFAULT_CURRENCY],
+            [("Alice", 55500), ("Bob", -25500), ("Carol", -30000)]
+                .iter()
+                .cloned()
+                .collect::<BTreeMap<&str, i64>>()
+        );
+        assert_eq!(
+            bills.settlement(),
+            vec![
+                Transfer {
+                    from: "Carol".to_string(),
+                    to: "Alice".to_string(),
+                    amount: usd(30000)
+                },
+                Transfer {
+                    from: "Bob".to_string(),
+                    to: "Alice".to_string(),
+                    amount: usd(25500)
+                },
+            ]
+        );
+        assert_eq!(
+            bills.remove_member("carol"),
+            Err(HouseholdError::MemberInUse {
+                member: "Carol".to_string(),
+                bill: "rent".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn check_menu_split_and_settle() {
+        #[rustfmt::skip]
+        let inputs = [
+            "members", "add Sam",
+            "members", "add Jo",
+            "add", "internet", "60", "2021-10-01", "monthly", "utilities",
+            "split", "internet", "percent", "sam 50, jo 40",
+            "percent", "sam 50, jo 50",
+            "pay", "internet", "pat", "jo", "2021-10-01", "full",
+            "settle",
+        ];
+        let (script, bills) = run_script("split_and_settle", &inputs);
+        assert!(script.contains("percentages add up to 90%, not 100%"));
+        assert!(script.contains("\"internet\" is now split Sam 50%, Jo 50%"));
+        assert!(script.contains("\"pat\" is not a household member"));
+        assert!(script.contains("Jo      is owed  30.00 USD"));
+        assert!(script.contains("Sam pays Jo 30.00 USD"));
+
+        let internet = bills.find("internet").unwrap();
+        assert_eq!(internet.payments[0].by, Some("Jo".to_string()));
+        assert_eq!(bills.members.iter().collect::<Vec<_>>(), vec!["Jo", "Sam"]);
+    }
 }
 
 // // === L2 Attempt
@@ -25,12 +25,26 @@
 // * Make your program robust: there are 7 errors & multiple blank lines
 //   present in the data.
 
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+#[path = "common/csv_util.rs"]
+mod csv_util;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::terminal;
+use csv_util::{csv_row, read_csv, CsvError};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 use thiserror::Error;
+use tui::backend::CrosstermBackend;
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph};
+use tui::{Frame, Terminal};
 
 /// A contact record.
 #[derive(Debug)]
@@ -41,12 +55,282 @@ struct Record {
     name: String,
     /// The email of the contact.
     email: Option<String>,
+    /// The phone numbers of the contact.
+    phones: Vec<Phone>,
+    /// The postal address of the contact.
+    address: Option<String>,
+    /// Free-form tags used to group contacts, such as "family" or "work".
+    tags: Vec<String>,
+    /// Any notes about the contact.
+    notes: Option<String>,
+    /// Values of columns in the data file that this program doesn't use,
+    /// keyed by column name. They are kept so they can be saved again.
+    extra: BTreeMap<String, String>,
+}
+
+impl Record {
+    /// Create a new record without any of the optional details.
+    fn new(id: i64, name: &str, email: Option<String>) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            email,
+            phones: vec![],
+            address: None,
+            tags: vec![],
+            notes: None,
+            extra: BTreeMap::new(),
+        }
+    }
+
+    /// Checks whether the record has a tag, ignoring case.
+    fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag.trim()))
+    }
+
+    /// Returns the value of a column for this record, or None if it is empty.
+    fn field(&self, column: &str) -> Option<String> {
+        // Lists of phones and tags are stored in a single field, separated
+        // by semicolons.
+        let list = |items: Vec<String>| Some(items.join("; ")).filter(|s| !s.is_empty());
+        match column {
+            "id" => Some(self.id.to_string()),
+            "name" => Some(self.name.clone()),
+            "email" => self.email.clone(),
+            "phone" => list(self.phones.iter().map(|p| p.to_string()).collect()),
+            "address" => self.address.clone(),
+            "tags" => list(self.tags.clone()),
+            "notes" => self.notes.clone(),
+            _ => self.extra.get(column).cloned(),
+        }
+    }
+
+    /// Merges the details of another record into this one. Details this
+    /// record already has are kept, while phones, tags and notes are combined.
+    fn merge(&mut self, other: Record) {
+        if self.email.is_none() {
+            self.email = other.email;
+        }
+        for phone in other.phones {
+            if !self.phones.iter().any(|p| p.number == phone.number) {
+                self.phones.push(phone);
+            }
+        }
+        if self.address.is_none() {
+            self.address = other.address;
+        }
+        self.tags.extend(other.tags);
+        self.tags = dedup_tags(std::mem::take(&mut self.tags));
+        self.notes = match (self.notes.take(), other.notes) {
+            (Some(a), Some(b)) if a != b => Some(format!("{}\n{}", a, b)),
+            (a, b) => a.or(b),
+        };
+        for (column, value) in other.extra {
+            self.extra.entry(column).or_insert(value);
+        }
+    }
+}
+
+impl Record {
+    /// Scores how well a search term matches the record, from 0 (no match)
+    /// to 1. Without a field, the best matching field is used.
+    fn match_score(&self, field: Option<&str>, term: &str) -> f64 {
+        let score = |column: &str| match self.field(column) {
+            Some(value) if column == "id" => (value == term) as u8 as f64,
+            Some(value) if column == "phone" => phone_score(term, &value),
+            Some(value) => match_score(term, &value),
+            None => 0.0,
+        };
+        match field {
+            Some(column) => score(column),
+            None => Header::KNOWN[1..]
+                .iter()
+                .copied()
+                .chain(self.extra.keys().map(|k| k.as_str()))
+                .map(score)
+                .fold(0.0, f64::max),
+        }
+    }
+}
+
+impl fmt::Display for Record {
+    /// Shows the record on one line, like "12: Jane Doe <jane@example.com>".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.id, self.name)?;
+        if let Some(email) = &self.email {
+            write!(f, " <{}>", email)?;
+        }
+        Ok(())
+    }
+}
+
+/// Separates the items of list fields, like phones and tags, in the data file.
+const LIST_SEPARATOR: char = ';';
+
+/// A phone number with an optional label, such as "work" or "mobile".
+#[derive(Debug, Clone, PartialEq)]
+struct Phone {
+    label: Option<String>,
+    number: String,
+}
+
+impl std::str::FromStr for Phone {
+    type Err = ParseError;
+
+    /// Parses a phone number written as "number" or "label:number".
+    fn from_str(phone: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseError::InvalidPhone(phone.to_string());
+        if phone.contains(LIST_SEPARATOR) {
+            return Err(invalid());
+        }
+        // "split_once" splits at the first ':' only, and returns None when
+        // there isn't one, which means there is no label.
+        let (label, number) = match phone.split_once(':') {
+            Some((label, number)) => (Some(label.trim()), number.trim()),
+            None => (None, phone.trim()),
+        };
+        if number.is_empty() || label == Some("") {
+            return Err(invalid());
+        }
+        Ok(Self {
+            label: label.map(|l| l.to_string()),
+            number: number.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Phone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.label {
+            Some(label) => write!(f, "{}:{}", label, self.number),
+            None => write!(f, "{}", self.number),
+        }
+    }
+}
+
+impl Phone {
+    /// Checks that the number looks like a phone number: digits with optional
+    /// spaces, dashes, dots and parentheses, and an optional leading '+'.
+    fn validate(&self) -> Result<(), ParseError> {
+        let number = self.number.strip_prefix('+').unwrap_or(&self.number);
+        let allowed = |c: char| c.is_ascii_digit() || matches!(c, ' ' | '-' | '.' | '(' | ')');
+        // E.164 allows at most 15 digits. Fewer than 3 can't be a number.
+        let digits = number.chars().filter(|c| c.is_ascii_digit()).count();
+        if number.chars().all(allowed) && (3..=15).contains(&digits) {
+            Ok(())
+        } else {
+            Err(ParseError::InvalidPhone(self.to_string()))
+        }
+    }
+}
+
+/// Checks the syntax of an email address, like "name@example.com".
+///
+/// This is a practical subset of RFC 5322: the part before the '@' may use
+/// letters, digits and a few symbols, and the domain must have at least two
+/// labels made of letters, digits and dashes.
+fn validate_email(email: &str) -> Result<(), ParseError> {
+    let invalid = || Err(ParseError::InvalidEmail(email.to_string()));
+    // "rsplit_once" splits at the last '@', so "a@b@c.com" leaves an '@'
+    // in the local part, which is rejected below.
+    let (local, domain) = match email.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return invalid(),
+    };
+    let local_ok = local.split('.').all(|part| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~-".contains(c))
+    });
+    let labels: Vec<_> = domain.split('.').collect();
+    let domain_ok = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    // The top level domain ("com", "edu", ...) is never numeric.
+    let tld_ok = labels
+        .last()
+        .is_some_and(|tld| tld.chars().any(|c| c.is_ascii_alphabetic()));
+    if local_ok && domain_ok && tld_ok {
+        Ok(())
+    } else {
+        invalid()
+    }
+}
+
+/// Checks the fields of a record that can be syntactically wrong.
+fn validate_record(record: &Record) -> Vec<ParseError> {
+    let mut errors = vec![];
+    // A record without a name can't be loaded again once it is saved.
+    if record.name.trim().is_empty() {
+        errors.push(ParseError::MissingField("name".to_owned()));
+    }
+    if let Some(email) = &record.email {
+        if let Err(e) = validate_email(email) {
+            errors.push(e);
+        }
+    }
+    for phone in record.phones.iter() {
+        if let Err(e) = phone.validate() {
+            errors.push(e);
+        }
+    }
+    errors
+}
+
+/// Parses and validates a phone number given on the command line.
+fn parse_phone(phone: &str) -> Result<Phone, ParseError> {
+    let phone: Phone = phone.parse()?;
+    phone.validate()?;
+    Ok(phone)
+}
+
+/// Validates an email given on the command line. An empty email is allowed
+/// because it is used to remove an email.
+fn parse_email(email: &str) -> Result<String, ParseError> {
+    let email = email.trim();
+    if !email.is_empty() {
+        validate_email(email)?;
+    }
+    Ok(email.to_string())
+}
+
+/// Parses a tag given on the command line.
+fn parse_tag(tag: &str) -> Result<String, String> {
+    let tag = tag.trim();
+    if tag.is_empty() {
+        Err("tags can't be empty".to_owned())
+    } else if tag.contains(LIST_SEPARATOR) {
+        Err(format!("tags can't contain '{}'", LIST_SEPARATOR))
+    } else {
+        Ok(tag.to_string())
+    }
+}
+
+/// Removes tags that appear more than once, ignoring case.
+fn dedup_tags(tags: Vec<String>) -> Vec<String> {
+    let mut unique: Vec<String> = vec![];
+    for tag in tags {
+        if !unique.iter().any(|t| t.eq_ignore_ascii_case(&tag)) {
+            unique.push(tag);
+        }
+    }
+    unique
 }
 
 /// Contains all saved records.
 #[derive(Debug)]
 struct Records {
     inner: HashMap<i64, Record>,
+    /// The columns of the data file the records were loaded from.
+    header: Header,
+    /// The raw text of the rows that couldn't be loaded. They are saved
+    /// unchanged after the records, so they can still be fixed by hand.
+    unparsed: Vec<String>,
 }
 
 impl Records {
@@ -54,19 +338,41 @@ impl Records {
     fn new() -> Self {
         Self {
             inner: HashMap::new(),
+            header: Header::default(),
+            unparsed: vec![],
         }
     }
 
     /// Edit an existing record. Will insert a new record if the id is not found.
     fn edit(&mut self, id: i64, name: &str, email: Option<String>) {
-        self.inner.insert(
-            id,
-            Record {
-                id,
-                name: name.to_string(),
-                email,
-            },
-        );
+        // "entry" gives us the existing record so its other details (phones,
+        // tags, extra columns, ...) are kept, or inserts a new one.
+        let record = self
+            .inner
+            .entry(id)
+            .or_insert_with(|| Record::new(id, name, None));
+        record.name = name.to_string();
+        record.email = email;
+    }
+
+    /// Returns the header to write the records with: the columns they were
+    /// loaded with, plus any known column a record uses that the data file
+    /// doesn't have yet (email, phone, tags, ...). Otherwise that data would
+    /// be silently dropped.
+    fn header_for_saving(&self) -> Header {
+        let mut header = self.header.clone();
+        for column in Header::KNOWN.iter() {
+            let used = self.inner.values().any(|rec| rec.field(column).is_some());
+            if used && header.position(column).is_none() {
+                header.columns.push(column.to_string());
+            }
+        }
+        header
+    }
+
+    /// Returns a record that can be changed in place.
+    fn get_mut(&mut self, id: i64) -> Option<&mut Record> {
+        self.inner.get_mut(&id)
     }
 
     /// Returns the next available record id.
@@ -74,7 +380,16 @@ impl Records {
         // First we just get all the keys (ids).
         // This vector is just a copy of the ids, so we can throw it
         // away when done.
-        let mut ids: Vec<_> = self.inner.keys().collect();
+        let mut ids: Vec<_> = self.inner.keys().copied().collect();
+        // The rows that couldn't be loaded are saved too, so their ids are
+        // still taken. Only the digits at the start of the row are used,
+        // since the rest of it may not make sense.
+        for text in self.unparsed.iter() {
+            let digits: String = text.chars().take_while(|c| c.is_ascii_digit()).collect();
+            if let Ok(id) = digits.parse() {
+                ids.push(id);
+            }
+        }
         ids.sort();
         // "pop" removes the last entry from the vector, so we will have
         // the largest ID currently in use.
@@ -103,20 +418,386 @@ impl Records {
         records
     }
 
-    /// Searches for all records containing the supplied name.
-    fn search(&self, name: &str) -> Vec<&Record> {
-        // We simple filter through the values here and see if there
-        // are any matches using the ".contains" method on a string.
-        // The search is case-insensitive due to the usage of ".to_lowercase".
-        self.inner
+    /// Returns the records chosen by the options, in the order they ask for.
+    /// Unlike "into_vec" the records stay in the collection, so this can be
+    /// used by commands that go on to change them.
+    fn list(&self, options: &ListOptions) -> Vec<&Record> {
+        let mut records: Vec<_> = self
+            .inner
             .values()
-            .filter(|rec| rec.name.to_lowercase().contains(&name.to_lowercase()))
+            .filter(|rec| options.matches(rec))
+            .collect();
+        records.sort_by(|a, b| options.sort.compare(a, b));
+        if options.reverse {
+            records.reverse();
+        }
+        // The filters are applied before paging, so every page is full.
+        records
+            .into_iter()
+            .skip(options.offset)
+            .take(options.limit.unwrap_or(usize::MAX))
             .collect()
     }
 
+    /// Searches for all records matching every term of the query. The best
+    /// matches come first, and records that match equally well are sorted by id.
+    fn search(&self, query: &Query) -> Result<Vec<&Record>, QueryError> {
+        // Scoped terms may use any column of the data file, so the field
+        // names are checked against the header, and extra columns are given
+        // the spelling used in the file.
+        let mut terms = vec![];
+        for term in query.terms.iter() {
+            let field = match &term.field {
+                Some(field) if is_known_column(field) => Some(field.clone()),
+                Some(field) => match self
+                    .header
+                    .columns
+                    .iter()
+                    .find(|c| c.eq_ignore_ascii_case(field))
+                {
+                    Some(column) => Some(column.clone()),
+                    None => return Err(QueryError::UnknownField(field.clone())),
+                },
+                None => None,
+            };
+            terms.push((field, term.value.as_str()));
+        }
+
+        let mut results: Vec<_> = self
+            .inner
+            .values()
+            .filter_map(|rec| {
+                // Every term has to match. The score of the record is the
+                // average score of its terms.
+                let mut total = 0.0;
+                for (field, value) in terms.iter() {
+                    let score = rec.match_score(field.as_deref(), value);
+                    if score == 0.0 {
+                        return None;
+                    }
+                    total += score;
+                }
+                Some((total / terms.len().max(1) as f64, rec))
+            })
+            .collect();
+        // "partial_cmp" is needed because floating point numbers can't always
+        // be compared, but scores are never NaN so the "unwrap_or" is never used.
+        results.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .partial_cmp(a_score)
+                .unwrap_or(Ordering::Equal)
+                .then(a.id.cmp(&b.id))
+        });
+        Ok(results.into_iter().map(|(_, rec)| rec).collect())
+    }
+
     fn remove(&mut self, id: i64) -> Option<Record> {
         self.inner.remove(&id)
     }
+
+    /// Finds groups of records that are probably the same person: records
+    /// with the same email, or with names at least `threshold` similar
+    /// (from 0 to 1). Each group is sorted by id, and the groups are sorted
+    /// by their lowest id.
+    fn duplicates(&self, threshold: f64) -> Vec<Vec<i64>> {
+        let mut recs: Vec<_> = self.inner.values().collect();
+        recs.sort_by_key(|rec| rec.id);
+        // The names and emails are normalized once up front, since every
+        // record is compared with every other record.
+        let names: Vec<_> = recs.iter().map(|rec| normalize_name(&rec.name)).collect();
+        let lengths: Vec<_> = names.iter().map(|name| name.chars().count()).collect();
+        let emails: Vec<_> = recs
+            .iter()
+            .map(|rec| rec.email.as_deref().map(normalize_email))
+            .collect();
+
+        // Every record starts in its own group. When two records match, the
+        // whole group of the second one joins the group of the first.
+        let mut group: Vec<usize> = (0..recs.len()).collect();
+        for a in 0..recs.len() {
+            for b in a + 1..recs.len() {
+                let same_email = emails[a].is_some() && emails[a] == emails[b];
+                // Names whose lengths differ too much can't be similar enough,
+                // which saves computing the similarity for most pairs.
+                let longest = lengths[a].max(lengths[b]) as f64;
+                let difference = (lengths[a] as f64 - lengths[b] as f64).abs();
+                let may_match = longest == 0.0 || 1.0 - difference / longest >= threshold;
+                if same_email || (may_match && similarity(&names[a], &names[b]) >= threshold) {
+                    let (from, to) = (group[b], group[a]);
+                    for g in group.iter_mut().filter(|g| **g == from) {
+                        *g = to;
+                    }
+                }
+            }
+        }
+
+        // A BTreeMap keeps the groups in order of their lowest id, since the
+        // group number is the index of its first record.
+        let mut groups: BTreeMap<usize, Vec<i64>> = BTreeMap::new();
+        for (i, g) in group.into_iter().enumerate() {
+            groups.entry(g).or_default().push(recs[i].id);
+        }
+        groups.into_values().filter(|ids| ids.len() > 1).collect()
+    }
+
+    /// Merges records into the one with the lowest id, which keeps its
+    /// name. Returns the id of the merged record, or None if none of the
+    /// records exist.
+    fn merge(&mut self, ids: &[i64]) -> Option<i64> {
+        let mut ids = ids.to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+        let mut merged: Option<Record> = None;
+        for id in ids {
+            if let Some(record) = self.inner.remove(&id) {
+                match merged.as_mut() {
+                    Some(merged) => merged.merge(record),
+                    None => merged = Some(record),
+                }
+            }
+        }
+        let merged = merged?;
+        let id = merged.id;
+        self.add(merged);
+        Some(id)
+    }
+}
+
+/// Normalizes an email for comparison. Case is ignored, as is a "+tag"
+/// suffix on the name, since "Jane+news@x.com" goes to "jane@x.com".
+fn normalize_email(email: &str) -> String {
+    let email = email.trim().to_lowercase();
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            let local = local.split('+').next().unwrap_or(local);
+            format!("{}@{}", local, domain)
+        }
+        None => email,
+    }
+}
+
+/// Normalizes a name for comparison. Case and punctuation are ignored, and
+/// the words are sorted so "Smith, John" matches "John Smith".
+fn normalize_name(name: &str) -> String {
+    let name: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    let mut words: Vec<_> = name.split_whitespace().collect();
+    words.sort_unstable();
+    words.join(" ")
+}
+
+/// Returns how similar two strings are, from 0 (nothing in common) to
+/// 1 (the same), based on the edit distance between them. Inserting,
+/// removing or changing a character is one edit, and so is swapping two
+/// neighbouring characters, which is a common typo ("jhon").
+fn similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    // "row[j]" holds the number of edits needed to turn the first i
+    // characters of "a" into the first j characters of "b". Only the
+    // two previous rows are needed to compute the next one.
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            row[j] = (previous[j] + 1)
+                .min(row[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut previous, row);
+    }
+    1.0 - previous[b.len()] as f64 / longest as f64
+}
+
+/// How similar a search term must be to a word to count as a typo of it.
+const FUZZY_THRESHOLD: f64 = 0.75;
+
+/// Scores how well a lowercase search term matches a value, from 0 (no
+/// match) to 1 (the whole value). Matching a whole word scores better than
+/// matching the start of a word, then the middle of one, and then a match
+/// with typos.
+fn match_score(term: &str, value: &str) -> f64 {
+    let value = value.to_lowercase();
+    if value == term {
+        return 1.0;
+    }
+    let words: Vec<_> = value
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.contains(&term) {
+        return 0.95;
+    }
+    if words.iter().any(|word| word.starts_with(term)) {
+        return 0.9;
+    }
+    if value.contains(term) {
+        return 0.8;
+    }
+    // Short terms would match far too many words with a typo or two.
+    if term.chars().count() < 4 {
+        return 0.0;
+    }
+    let best = words
+        .iter()
+        .map(|word| similarity(term, word))
+        .fold(similarity(term, &value), f64::max);
+    if best >= FUZZY_THRESHOLD {
+        0.7 * best
+    } else {
+        0.0
+    }
+}
+
+/// Scores a search term against phone numbers. Only the digits are compared
+/// when the term is a number, so "5550100" matches "555-0100".
+fn phone_score(term: &str, phones: &str) -> f64 {
+    let digits = |s: &str| -> String { s.chars().filter(|c| c.is_ascii_digit()).collect() };
+    let term_digits = digits(term);
+    let is_number = term
+        .chars()
+        .all(|c| c.is_ascii_digit() || "+-.() ".contains(c));
+    if is_number && !term_digits.is_empty() {
+        let found = phones
+            .split(LIST_SEPARATOR)
+            .any(|phone| digits(phone).contains(&term_digits));
+        if found {
+            return 0.9;
+        }
+    }
+    match_score(term, phones)
+}
+
+/// Errors in a search query.
+#[derive(Error, Debug, PartialEq)]
+enum QueryError {
+    #[error("unknown field {0:?}")]
+    UnknownField(String),
+    #[error("quote is never closed")]
+    UnterminatedQuote,
+}
+
+/// One term of a search query, optionally limited to a single field.
+#[derive(Debug, PartialEq)]
+struct Term {
+    field: Option<String>,
+    /// The lowercase text to look for.
+    value: String,
+}
+
+/// A search query, made of terms separated by spaces.
+///
+/// A term like `email:@msu.edu` only searches the email field, while a term
+/// without a field searches every field. Quotes keep spaces in a term, as in
+/// `name:"van der"`.
+#[derive(Debug, PartialEq)]
+struct Query {
+    terms: Vec<Term>,
+}
+
+impl std::str::FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        let mut terms = vec![];
+        let mut chars = query.chars().peekable();
+        loop {
+            // Skip the spaces between terms.
+            while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                chars.next();
+            }
+            if chars.peek().is_none() {
+                break;
+            }
+            let mut field = None;
+            let mut value = String::new();
+            let mut in_quotes = false;
+            let mut quoted = false;
+            for c in chars.by_ref() {
+                match c {
+                    '"' => {
+                        in_quotes = !in_quotes;
+                        quoted = true;
+                    }
+                    // Only the first ':' before any quote separates the
+                    // field, so "email:a:b" looks for "a:b" in the email.
+                    ':' if field.is_none() && !quoted && !value.is_empty() => {
+                        field = Some(std::mem::take(&mut value));
+                    }
+                    c if c.is_whitespace() && !in_quotes => break,
+                    c => value.push(c),
+                }
+            }
+            if in_quotes {
+                return Err(QueryError::UnterminatedQuote);
+            }
+            let field = field.map(|field: String| {
+                // A few other spellings of the known fields are accepted.
+                match field.to_lowercase().as_str() {
+                    "tag" => "tags".to_owned(),
+                    "phones" => "phone".to_owned(),
+                    "note" => "notes".to_owned(),
+                    field => field.to_owned(),
+                }
+            });
+            terms.push(Term {
+                field,
+                value: value.to_lowercase(),
+            });
+        }
+        Ok(Self { terms })
+    }
+}
+
+/// A field to sort records by.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum SortKey {
+    #[default]
+    Id,
+    Name,
+    Email,
+}
+
+impl SortKey {
+    /// Compares two records by this key, ignoring case. Records without an
+    /// email come last, and ties are sorted by id.
+    fn compare(&self, a: &Record, b: &Record) -> Ordering {
+        let lower = |s: &Option<String>| s.as_ref().map(|s| s.to_lowercase());
+        let order = match self {
+            SortKey::Id => Ordering::Equal,
+            SortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            SortKey::Email => match (lower(&a.email), lower(&b.email)) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            },
+        };
+        order.then(a.id.cmp(&b.id))
+    }
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        match key.to_lowercase().as_str() {
+            "id" => Ok(SortKey::Id),
+            "name" => Ok(SortKey::Name),
+            "email" => Ok(SortKey::Email),
+            _ => Err(format!("can't sort by {:?}, use id, name or email", key)),
+        }
+    }
 }
 
 /// Errors that may occur while parsing the data file.
@@ -128,210 +809,2309 @@ enum ParseError {
     EmptyRecord,
     #[error("missing field: {0}")]
     MissingField(String),
+    #[error("header is missing the {0:?} column")]
+    MissingColumn(String),
+    #[error("header has more than one {0:?} column")]
+    DuplicateColumn(String),
+    #[error("header has a column without a name")]
+    UnnamedColumn,
+    #[error("record has {found} fields but the header only has {expected}")]
+    TooManyFields { found: usize, expected: usize },
+    #[error("invalid phone number: {0:?}")]
+    InvalidPhone(String),
+    #[error("invalid email: {0:?}")]
+    InvalidEmail(String),
+    #[error("{0}")]
+    Csv(#[from] CsvError),
+}
+
+impl ParseError {
+    /// Returns the field the error is about, used in validation reports.
+    /// Errors about the whole record have no field.
+    fn field(&self) -> &str {
+        match self {
+            ParseError::InvalidId(_) | ParseError::EmptyRecord => "id",
+            ParseError::MissingField(field) => field,
+            ParseError::MissingColumn(_)
+            | ParseError::DuplicateColumn(_)
+            | ParseError::UnnamedColumn => "header",
+            ParseError::InvalidPhone(_) => "phone",
+            ParseError::InvalidEmail(_) => "email",
+            ParseError::TooManyFields { .. } | ParseError::Csv(_) => "",
+        }
+    }
+}
+
+/// A problem found in a row of the data file.
+#[derive(Debug)]
+struct Problem {
+    /// Line number the row starts on.
+    line: usize,
+    /// The raw text of the row.
+    text: String,
+    error: ParseError,
+    /// Whether the row was skipped, or the record was loaded anyway.
+    skipped: bool,
+}
+
+/// The columns of the data file, in the order they appear.
+///
+/// The first row of the data file names the columns, which lets the columns
+/// appear in any order and lets the file contain columns this program doesn't
+/// know about. Those are kept in `Record::extra` and written back on save.
+#[derive(Debug, Clone)]
+struct Header {
+    columns: Vec<String>,
+}
+
+impl Header {
+    /// Columns that every data file must have.
+    const REQUIRED: [&'static str; 2] = ["id", "name"];
+    /// Columns that are stored in `Record` fields, in the order they are
+    /// added to a data file that doesn't have them yet.
+    const KNOWN: [&'static str; 7] = ["id", "name", "email", "phone", "address", "tags", "notes"];
+
+    /// Creates a header from the fields of the first row.
+    fn parse(fields: &[String]) -> Result<Self, ParseError> {
+        let mut columns: Vec<String> = Vec::new();
+        for field in fields {
+            // Surrounding whitespace and case are ignored for the known
+            // columns, so " ID" and "Email" are accepted too.
+            let column = field.trim();
+            let known = column.to_lowercase();
+            let column = if is_known_column(&known) {
+                known
+            } else {
+                column.to_string()
+            };
+            if column.is_empty() {
+                return Err(ParseError::UnnamedColumn);
+            }
+            if columns.contains(&column) {
+                return Err(ParseError::DuplicateColumn(column));
+            }
+            columns.push(column);
+        }
+        let header = Self { columns };
+        for column in Self::REQUIRED.iter() {
+            if header.position(column).is_none() {
+                return Err(ParseError::MissingColumn(column.to_string()));
+            }
+        }
+        Ok(header)
+    }
+
+    /// Checks whether a row is a header rather than a record. Older data
+    /// files have no header, and their first row is just a record.
+    fn is_header(fields: &[String]) -> bool {
+        fields
+            .iter()
+            .any(|field| field.trim().eq_ignore_ascii_case("id"))
+    }
+
+    /// Returns the index of a column.
+    fn position(&self, column: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c == column)
+    }
+
+    /// Returns the fields of a record in column order.
+    fn fields(&self, record: &Record) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|column| record.field(column).unwrap_or_default())
+            .collect()
+    }
+}
+
+impl Default for Header {
+    /// The columns used when a data file has no header.
+    fn default() -> Self {
+        Self {
+            columns: vec!["id".to_owned(), "name".to_owned(), "email".to_owned()],
+        }
+    }
 }
 
-/// Parses a single record line.
-fn parse_record(record: &str) -> Result<Record, ParseError> {
-    // We use ".split" on ',' to create a vector of strings.
-    // This vector will contain elements of each field in the record
-    // without the delimiting commas separating them.
-    let fields: Vec<&str> = record.split(',').collect();
+/// Parses the fields of a single record, using the header to find each field.
+fn parse_record(header: &Header, fields: &[String]) -> Result<Record, ParseError> {
+    // A record with more fields than the header has data we wouldn't know
+    // where to save, so it is rejected. Fewer fields are fine: the missing
+    // trailing fields are treated as empty.
+    if fields.len() > header.columns.len() {
+        return Err(ParseError::TooManyFields {
+            found: fields.len(),
+            expected: header.columns.len(),
+        });
+    }
+    // This closure returns the field in the given column. "and_then" is used
+    // because both the column and the field may be missing.
+    let field = |column: &str| header.position(column).and_then(|i| fields.get(i));
 
     // The id and name fields are required, so a match expression is used
     // in order to extract the data (if possible) and place it into the
     // appropriate variables.
 
-    // Here we try to get the "id" portion of the record, which should be
-    // the first entry (which is index 0). We then use "from_str_radix"
+    // Here we try to get the "id" portion of the record. We then use "parse"
     // to parse the string id into a numeric i64 id. We abort the function
     // if this conversion fails, or if we do not find the id.
-    let id = match fields.get(0) {
-        Some(id) => i64::from_str_radix(id, 10)?,
+    let id = match field("id") {
+        Some(id) => id.parse::<i64>()?,
         None => return Err(ParseError::EmptyRecord),
     };
 
-    // Here we try to get the "name" portion of the record, which should
-    // be the second entry (which is index 1). We also ensure that a name
-    // actually exists by using filter on the name and seeing if it is an
-    // empty string (""). The "fields" vector contains &str type, "get"
-    // references again (&&str) and then filter references a third time (&&&str).
-    // The asterisks (**) remove two of these references so we can compare
-    // to the empty string ("") which is a &str. This is not something to
-    // worry about because the compiler will tell you if asterisks are needed
-    // when you attempt to compile the program.
-    let name = match fields.get(1).filter(|name| **name != "") {
+    // Here we try to get the "name" portion of the record. We also ensure
+    // that a name actually exists by using filter on the name and seeing
+    // if it is an empty string ("").
+    let name = match field("name").filter(|name| !name.is_empty()) {
         Some(name) => name.to_string(),
         None => return Err(ParseError::MissingField("name".to_owned())),
     };
 
-    // The email field is the third piece of data (index 2). "Get" returns
-    // an Option, so all we need to do is simply map the email to a
-    // String type with "to_string" and then filter if it is empty.
+    // "Get" returns an Option, so all we need to do is simply map the email
+    // to a String type with "to_string" and then filter if it is empty.
     // Map and filter will only run if we actually have data to work
     // with, since emails are optional.
-    let email = fields
-        .get(2)
+    let email = field("email")
         .map(|email| email.to_string())
-        .filter(|email| email != "");
+        .filter(|email| !email.is_empty());
+
+    // The address and notes work just like the email.
+    let address = field("address")
+        .map(|address| address.to_string())
+        .filter(|address| !address.is_empty());
+    let notes = field("notes")
+        .map(|notes| notes.to_string())
+        .filter(|notes| !notes.is_empty());
+
+    // Phones and tags can have several entries separated by semicolons.
+    // Collecting into a Result stops at the first phone that fails to parse.
+    let list = |column| -> Vec<&str> {
+        match field(column) {
+            Some(value) => value
+                .split(LIST_SEPARATOR)
+                .map(|item| item.trim())
+                .filter(|item| !item.is_empty())
+                .collect(),
+            None => vec![],
+        }
+    };
+    let phones = list("phone")
+        .into_iter()
+        .map(|phone| phone.parse())
+        .collect::<Result<_, _>>()?;
+    let tags = dedup_tags(list("tags").into_iter().map(|t| t.to_string()).collect());
+
+    // Everything in the remaining columns is kept as-is. Empty values are
+    // skipped since they are saved as empty fields anyway.
+    let extra = header
+        .columns
+        .iter()
+        .zip(fields.iter())
+        .filter(|(column, value)| !value.is_empty() && !is_known_column(column))
+        .map(|(column, value)| (column.clone(), value.clone()))
+        .collect();
+
+    Ok(Record {
+        id,
+        name,
+        email,
+        phones,
+        address,
+        tags,
+        notes,
+        extra,
+    })
+}
 
-    Ok(Record { id, name, email })
+/// Checks whether a column is stored in a `Record` field.
+fn is_known_column(column: &str) -> bool {
+    Header::KNOWN.contains(&column)
 }
 
 /// Parses the entire record file.
-fn parse_records(records: String, verbose: bool) -> Records {
+fn parse_records(records: String, verbose: bool) -> Result<Records, ParseError> {
+    let (recs, problems) = parse_rows(&records)?;
+    if verbose {
+        // Only the rows that were skipped are shown here. Use the "validate"
+        // command to see every problem.
+        for problem in problems.iter().filter(|p| p.skipped) {
+            println!(
+                "error on line number {}: {}\n  > \"{}\"\n",
+                problem.line, problem.error, problem.text
+            );
+        }
+    }
+    Ok(recs)
+}
+
+/// Parses the rows of the record file, returning the records along with
+/// every problem found. Records with invalid emails or phone numbers are
+/// still loaded so that they aren't lost when saving.
+fn parse_rows(records: &str) -> Result<(Records, Vec<Problem>), ParseError> {
     let mut recs = Records::new();
-    // We use ".split" with '\n' to get each line one at a time.
-    // '\n' means "new line". "Enumerate" provides the data and
-    // the current enumeration index (starting from 0) and we use
-    // this number to report line errors.
-    for (num, record) in records.split('\n').enumerate() {
-        if record != "" {
-            match parse_record(record) {
-                Ok(rec) => recs.add(rec),
-                Err(e) => {
-                    if verbose {
-                        println!(
-                            "error on line number {}: {}\n  > \"{}\"\n",
-                            num + 1,
-                            e,
-                            record
-                        );
-                    }
+    let mut problems = vec![];
+    let mut rows = read_csv(records).into_iter().peekable();
+
+    // The first row describes the columns. If it doesn't look like a header
+    // the default "id,name,email" columns are used and the row is read as
+    // a record.
+    if let Some(Ok(fields)) = rows.peek().map(|row| &row.fields) {
+        if Header::is_header(fields) {
+            recs.header = Header::parse(fields)?;
+            rows.next();
+        }
+    }
+
+    // "read_csv" splits the data into rows and fields for us. A field can
+    // contain a line break when quoted, so each row remembers the line
+    // number it started on, which we use to report errors.
+    for row in rows {
+        // "?" can't be used here since we want to keep going, so the CSV
+        // error is converted into a ParseError with "into" instead.
+        let result = match row.fields {
+            Ok(fields) => parse_record(&recs.header, &fields),
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(rec) => {
+                for error in validate_record(&rec) {
+                    problems.push(Problem {
+                        line: row.line,
+                        text: row.text.clone(),
+                        error,
+                        skipped: false,
+                    });
                 }
+                recs.add(rec);
+            }
+            Err(error) => {
+                recs.unparsed.push(row.text.clone());
+                problems.push(Problem {
+                    line: row.line,
+                    text: row.text,
+                    error,
+                    skipped: true,
+                });
             }
         }
     }
-    recs
+    Ok((recs, problems))
+}
+
+/// Checks the whole record file and returns every problem found, as
+/// (line, field, error) rows. A bad header is reported on line 1.
+fn validate(records: &str) -> Vec<(usize, String, String)> {
+    match parse_rows(records) {
+        Ok((_, problems)) => problems
+            .iter()
+            .map(|p| (p.line, p.error.field().to_string(), p.error.to_string()))
+            .collect(),
+        Err(e) => vec![(1, e.field().to_string(), e.to_string())],
+    }
+}
+
+/// Checks records loaded from a database. A database has no lines, so the
+/// problems are returned as (id, field, error) rows, sorted by id.
+fn validate_records(records: &Records) -> Vec<(i64, String, String)> {
+    let mut problems = vec![];
+    for record in records.list(&ListOptions::default()) {
+        for error in validate_record(record) {
+            problems.push((record.id, error.field().to_string(), error.to_string()));
+        }
+    }
+    problems
 }
 
 /// Loads the raw records from a file.
 fn load_records(file_name: PathBuf, verbose: bool) -> std::io::Result<Records> {
+    let buffer = read_file(file_name)?;
+
+    // A bad header means we can't make sense of any of the records, so it
+    // is reported as an error instead of skipping lines like above.
+    parse_records(buffer, verbose)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Reads the whole data file into a string.
+fn read_file(file_name: PathBuf) -> std::io::Result<String> {
     let mut file = File::open(file_name)?;
 
     let mut buffer = String::new();
     file.read_to_string(&mut buffer)?;
-
-    Ok(parse_records(buffer, verbose))
+    Ok(buffer)
 }
 
 /// Saves the records to disk.
 fn save_records(file_name: PathBuf, records: Records) -> std::io::Result<()> {
-    // We use OpenOptions to configure how the file should be opened.
-    // This is needed so we can get write access to the file. Additionally,
-    // we "truncate" the file, which deletes all the contents. This is done
-    // because we rewrite the entire contents whenever we save. It is possible
-    // to write to a specific section of the file, but rewriting the entire
-    // file is the simplest method.
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(file_name)?;
-
-    // First we write the field names.
-    file.write(b"id,name,email\n")?;
-
-    // Then we iterate through each record and write it to the file.
+    // The whole file is built in memory first and then written in one go
+    // by "write_atomic", so a failure halfway through can't leave a file
+    // with only some of the records in it.
+    let mut contents = String::new();
+
+    // First we write the field names, in the same order they were loaded.
+    // The header and the unparsed rows are taken before "into_vec" below
+    // consumes the records.
+    let header = records.header_for_saving();
+    let unparsed = records.unparsed.clone();
+    let columns: Vec<_> = header.columns.iter().map(|c| c.as_str()).collect();
+    contents.push_str(&csv_row(&columns));
+
+    // Then we iterate through each record and add it to the contents.
     // "Into_iter" creates an iterator that takes ownership of the data
     // during iteration. We do this so we don't have to make additional
     // copies of the data before saving it to disk (we can just work with
     // it directly).
     for record in records.into_vec().into_iter() {
-        // When we do not have an email, we just use an empty string ("").
-        let email = match record.email {
-            Some(email) => email,
-            None => "".to_string(),
-        };
-        // This creates a new string that is properly formatted to CSV.
-        let line = format!("{},{},{}\n", record.id, record.name, email);
-        // We then write the string to the file. "write" works with bytes,
-        // so we just access the bytes of the string with "as_bytes".
-        file.write(line.as_bytes())?;
-    }
-    // "Flushing" the data ensures that everything is written to disk before
-    // continuing. Without this line, it is possible for the program to
-    // terminate before the system is done writing to the file, and this
-    // can result in corrupted data.
-    file.flush()?;
+        // The header puts each field in its column. Missing values, like
+        // an empty email, are written as empty strings ("").
+        let fields = header.fields(&record);
+        let fields: Vec<_> = fields.iter().map(|f| f.as_str()).collect();
+        // This creates a new string that is properly formatted to CSV,
+        // quoting any fields that contain commas, quotes or line breaks.
+        contents.push_str(&csv_row(&fields));
+    }
+    // The rows that couldn't be loaded go last, exactly as they were read.
+    for text in unparsed {
+        contents.push_str(&text);
+        contents.push('\n');
+    }
+    // "write_all" works with bytes, so we just access the bytes of the
+    // string with "as_bytes".
+    write_atomic(&file_name, contents.as_bytes())
+}
+
+/// How many backups of the data file are kept. The oldest ones are deleted
+/// when there are more.
+const BACKUPS_TO_KEEP: usize = 10;
+
+/// Replaces the contents of a file without ever leaving it half written.
+///
+/// The new contents are written to a temporary file next to it, which is
+/// then renamed over the original. Renaming within a directory is atomic,
+/// so after a crash the file has either the old or the new contents. The
+/// old contents are kept as a timestamped backup first.
+fn write_atomic(file_name: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let temp_name = temp_path(file_name);
+    // If anything goes wrong the temporary file is removed again, and the
+    // original file hasn't been touched.
+    let result = write_temp(&temp_name, contents)
+        .and_then(|_| backup(file_name))
+        .and_then(|_| fs::rename(&temp_name, file_name));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_name);
+        return result;
+    }
+    // The rename itself is only durable once the directory is synced. This
+    // isn't possible on every platform, so errors are ignored.
+    if let Ok(dir) = File::open(parent_dir(file_name)) {
+        let _ = dir.sync_all();
+    }
+    // The new contents are saved by now, so failing to delete old backups
+    // is only worth a warning. Reporting it as an error would make it look
+    // like the save failed, and trying again would save the changes twice.
+    if let Err(e) = prune_backups(file_name) {
+        eprintln!("warning: unable to delete old backups: {}", e);
+    }
     Ok(())
 }
 
-#[derive(StructOpt, Debug)]
-#[structopt(about = "project 2: contact manager")]
-struct Opt {
-    #[structopt(short, parse(from_os_str), default_value = "p2_data.csv")]
-    data_file: PathBuf,
-    #[structopt(subcommand)]
-    cmd: Command,
-    #[structopt(short, help = "verbose")]
-    verbose: bool,
+/// Writes the contents to the temporary file and makes sure they are on disk.
+fn write_temp(temp_name: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut file = File::create(temp_name)?;
+    file.write_all(contents)?;
+    // "Flushing" hands the data over to the system, and "sync_all" waits
+    // until the system has actually written it to disk. Without this, the
+    // rename could reach the disk before the data does.
+    file.flush()?;
+    file.sync_all()
 }
 
-#[derive(StructOpt, Debug)]
-enum Command {
-    Add {
-        name: String,
-        #[structopt(short)]
-        email: Option<String>,
-    },
-    Edit {
-        id: i64,
-        name: String,
-        #[structopt(short)]
+/// Returns the directory a file is in. A bare file name is in the current
+/// directory, which "parent" returns as an empty path.
+fn parent_dir(file_name: &Path) -> &Path {
+    match file_name.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Returns the name of the temporary file used while saving, like
+/// ".p2_data.csv.tmp" for "p2_data.csv".
+fn temp_path(file_name: &Path) -> PathBuf {
+    let name = file_name.file_name().unwrap_or_default().to_string_lossy();
+    parent_dir(file_name).join(format!(".{}.tmp", name))
+}
+
+/// Returns the directory backups are kept in, like "p2_data_backups" for
+/// "p2_data.csv".
+fn backup_dir(file_name: &Path) -> PathBuf {
+    let stem = file_name.file_stem().unwrap_or_default().to_string_lossy();
+    parent_dir(file_name).join(format!("{}_backups", stem))
+}
+
+/// Copies the file into the backup directory, named with the current UTC
+/// time like "p2_data-20211005-143000.123456.csv". Returns the backup, or None
+/// when there is no file to back up yet.
+fn backup(file_name: &Path) -> std::io::Result<Option<PathBuf>> {
+    if !file_name.exists() {
+        return Ok(None);
+    }
+    let dir = backup_dir(file_name);
+    fs::create_dir_all(&dir)?;
+    let stem = file_name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = file_name.extension().unwrap_or_default().to_string_lossy();
+    // The timestamp sorts in the same order as time, so the names of the
+    // backups can be sorted to find the newest ones. UTC is used because
+    // local time goes back an hour when daylight saving time ends, which
+    // would make newer backups sort before older ones.
+    let timestamp = chrono::Utc::now().format("%Y%m%d-%H%M%S%.6f");
+    let backup = dir.join(format!("{}-{}.{}", stem, timestamp, extension));
+    fs::copy(file_name, &backup)?;
+    Ok(Some(backup))
+}
+
+/// Returns the backups of a file, newest first.
+fn list_backups(file_name: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let dir = backup_dir(file_name);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let prefix = format!(
+        "{}-",
+        file_name.file_stem().unwrap_or_default().to_string_lossy()
+    );
+    let mut backups = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_file() && name.starts_with(&prefix) {
+            backups.push(path);
+        }
+    }
+    backups.sort();
+    backups.reverse();
+    Ok(backups)
+}
+
+/// Deletes the oldest backups so only `BACKUPS_TO_KEEP` are left.
+fn prune_backups(file_name: &Path) -> std::io::Result<()> {
+    for old in list_backups(file_name)?.iter().skip(BACKUPS_TO_KEEP) {
+        fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+/// Restores the data file from a backup. The current file is backed up
+/// first, so a restore can be undone by restoring that backup.
+fn restore_backup(file_name: &Path, backup: &Path) -> std::io::Result<()> {
+    let contents = fs::read(backup)?;
+    write_atomic(file_name, &contents)
+}
+
+/// Where the records are kept.
+///
+/// CSV files are easy to read and change by hand, but the whole file has to
+/// be written again on every change. An SQLite database only writes the
+/// records that changed, and its transactions keep two programs using the
+/// same database at once from overwriting each other's changes.
+trait Storage {
+    /// Loads all of the records.
+    fn load(&self, verbose: bool) -> std::io::Result<Records>;
+
+    /// Replaces all of the stored records with these ones.
+    fn save(&mut self, records: Records) -> std::io::Result<()>;
+
+    /// Saves the records after the ones in "changed" were added or edited
+    /// and the ones in "removed" were removed. Storages that can't change
+    /// single records save all of them.
+    fn update(
+        &mut self,
+        records: Records,
+        _changed: &[i64],
+        _removed: &[i64],
+    ) -> std::io::Result<()> {
+        self.save(records)
+    }
+}
+
+/// The kinds of storage a data file can use.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Csv,
+    Sqlite,
+}
+
+impl Backend {
+    /// Picks the backend from the extension of the data file. ".db",
+    /// ".sqlite" and ".sqlite3" files are SQLite databases, and anything
+    /// else is CSV.
+    fn for_file(file_name: &Path) -> Self {
+        let extension = file_name.extension().unwrap_or_default().to_string_lossy();
+        match extension.to_lowercase().as_str() {
+            "db" | "sqlite" | "sqlite3" => Backend::Sqlite,
+            _ => Backend::Csv,
+        }
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(backend: &str) -> Result<Self, Self::Err> {
+        match backend.to_lowercase().as_str() {
+            "csv" => Ok(Backend::Csv),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(format!("unknown backend {:?}, use csv or sqlite", backend)),
+        }
+    }
+}
+
+/// Opens the storage of a data file. Without a backend, it is picked from
+/// the file extension.
+fn open_storage(file_name: &Path, backend: Option<Backend>) -> std::io::Result<Box<dyn Storage>> {
+    match backend.unwrap_or_else(|| Backend::for_file(file_name)) {
+        Backend::Csv => Ok(Box::new(CsvStorage {
+            file_name: file_name.to_path_buf(),
+        })),
+        Backend::Sqlite => Ok(Box::new(SqliteStorage::open(file_name)?)),
+    }
+}
+
+/// Records kept in a CSV file, with a header row naming the columns.
+struct CsvStorage {
+    file_name: PathBuf,
+}
+
+impl Storage for CsvStorage {
+    fn load(&self, verbose: bool) -> std::io::Result<Records> {
+        load_records(self.file_name.clone(), verbose)
+    }
+
+    fn save(&mut self, records: Records) -> std::io::Result<()> {
+        save_records(self.file_name.clone(), records)
+    }
+}
+
+/// Records kept in an SQLite database.
+///
+/// The columns the program knows about are stored in the "contacts" table,
+/// and any other columns in the "extra" table. The "header" table keeps the
+/// order of the columns, so that migrating a CSV file to SQLite and back
+/// gives the same file.
+struct SqliteStorage {
+    conn: rusqlite::Connection,
+}
+
+impl SqliteStorage {
+    /// Creates the tables if the database doesn't have them yet. The
+    /// contacts table has a column for each of `Header::KNOWN`.
+    const SCHEMA: &'static str = "
+        CREATE TABLE IF NOT EXISTS header (
+            position INTEGER PRIMARY KEY,
+            name TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS contacts (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            email TEXT,
+            phone TEXT,
+            address TEXT,
+            tags TEXT,
+            notes TEXT
+        );
+        CREATE TABLE IF NOT EXISTS extra (
+            contact_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY (contact_id, name)
+        );";
+
+    /// Opens the database, creating it if it doesn't exist.
+    fn open(file_name: &Path) -> std::io::Result<Self> {
+        let open = || -> rusqlite::Result<rusqlite::Connection> {
+            let conn = rusqlite::Connection::open(file_name)?;
+            // Another program may be writing to the database. Instead of
+            // failing right away, we wait a while for it to finish.
+            conn.busy_timeout(std::time::Duration::from_secs(5))?;
+            conn.execute_batch(Self::SCHEMA)?;
+            Ok(conn)
+        };
+        Ok(Self {
+            conn: open().map_err(sqlite_error)?,
+        })
+    }
+
+    /// Reads the records, along with the header they were saved with.
+    fn read(&self, verbose: bool) -> rusqlite::Result<Records> {
+        let mut recs = Records::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name FROM header ORDER BY position")?;
+        let columns = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        // A new database has no header yet, so the default one is kept.
+        if !columns.is_empty() {
+            recs.header = Header { columns };
+        }
+
+        // Each record is turned back into the fields of a CSV row, so it is
+        // checked by "parse_record" just like when loading a CSV file.
+        let mut values: HashMap<i64, HashMap<String, String>> = HashMap::new();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM contacts",
+            Header::KNOWN.join(", ")
+        ))?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let mut fields = HashMap::new();
+            fields.insert("id".to_owned(), id.to_string());
+            for (i, column) in Header::KNOWN.iter().enumerate().skip(1) {
+                if let Some(value) = row.get::<_, Option<String>>(i)? {
+                    fields.insert(column.to_string(), value);
+                }
+            }
+            values.insert(id, fields);
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT contact_id, name, value FROM extra")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Some(fields) = values.get_mut(&row.get(0)?) {
+                fields.insert(row.get(1)?, row.get(2)?);
+            }
+        }
+
+        for (id, mut fields) in values {
+            let fields: Vec<_> = recs
+                .header
+                .columns
+                .iter()
+                .map(|column| fields.remove(column).unwrap_or_default())
+                .collect();
+            match parse_record(&recs.header, &fields) {
+                Ok(rec) => recs.add(rec),
+                // This only happens when the database was changed by
+                // another program.
+                Err(e) if verbose => println!("error in record {}: {}", id, e),
+                Err(_) => (),
+            }
+        }
+        Ok(recs)
+    }
+
+    /// Writes the records in one transaction, so that either all of the
+    /// changes are saved or none of them are. Without "changed", every
+    /// record is written again.
+    fn write(
+        &mut self,
+        records: &Records,
+        changed: Option<&[i64]>,
+        removed: &[i64],
+    ) -> rusqlite::Result<()> {
+        // "Immediate" locks the database for writing right away, rather
+        // than when the first change is made.
+        let tx = self
+            .conn
+            .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        tx.execute("DELETE FROM header", [])?;
+        let header = records.header_for_saving();
+        for (position, column) in header.columns.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO header (position, name) VALUES (?1, ?2)",
+                rusqlite::params![position as i64, column],
+            )?;
+        }
+
+        let ids: Vec<i64> = match changed {
+            Some(changed) => changed.to_vec(),
+            None => {
+                tx.execute_batch("DELETE FROM extra; DELETE FROM contacts;")?;
+                records.inner.keys().copied().collect()
+            }
+        };
+        for id in removed.iter().chain(ids.iter()) {
+            tx.execute("DELETE FROM extra WHERE contact_id = ?1", [id])?;
+            tx.execute("DELETE FROM contacts WHERE id = ?1", [id])?;
+        }
+        for record in ids.iter().filter_map(|id| records.inner.get(id)) {
+            tx.execute(
+                &format!(
+                    "INSERT INTO contacts ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    Header::KNOWN.join(", ")
+                ),
+                rusqlite::params![
+                    record.id,
+                    record.name,
+                    record.field("email"),
+                    record.field("phone"),
+                    record.field("address"),
+                    record.field("tags"),
+                    record.field("notes"),
+                ],
+            )?;
+            for (name, value) in record.extra.iter() {
+                tx.execute(
+                    "INSERT INTO extra (contact_id, name, value) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![record.id, name, value],
+                )?;
+            }
+        }
+        tx.commit()
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self, verbose: bool) -> std::io::Result<Records> {
+        self.read(verbose).map_err(sqlite_error)
+    }
+
+    fn save(&mut self, records: Records) -> std::io::Result<()> {
+        self.write(&records, None, &[]).map_err(sqlite_error)
+    }
+
+    fn update(
+        &mut self,
+        records: Records,
+        changed: &[i64],
+        removed: &[i64],
+    ) -> std::io::Result<()> {
+        self.write(&records, Some(changed), removed)
+            .map_err(sqlite_error)
+    }
+}
+
+/// Converts an SQLite error so it can be returned along with the errors of
+/// reading and writing files.
+fn sqlite_error(error: rusqlite::Error) -> std::io::Error {
+    std::io::Error::other(error)
+}
+
+/// The ways contacts can be shown by the list and search commands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    /// Aligned columns, for reading.
+    Table,
+    /// A JSON array of contacts.
+    Json,
+    /// One JSON object per line, for tools that process line by line.
+    JsonLines,
+    /// The same CSV format as the data file.
+    Csv,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!(
+                "unknown format {:?}, use table, json, jsonl or csv",
+                format
+            )),
+        }
+    }
+}
+
+/// Formats records for output. The header is only used for the CSV format,
+/// so the columns match the data file.
+fn format_records(records: &[&Record], format: OutputFormat, header: &Header) -> String {
+    match format {
+        OutputFormat::Table => format_table(records),
+        OutputFormat::Json => {
+            // Each object goes on its own line, indented inside the array.
+            let objects: Vec<_> = records
+                .iter()
+                .map(|rec| format!("  {}", to_json(rec)))
+                .collect();
+            if objects.is_empty() {
+                "[]\n".to_owned()
+            } else {
+                format!("[\n{}\n]\n", objects.join(",\n"))
+            }
+        }
+        OutputFormat::JsonLines => records
+            .iter()
+            .map(|rec| format!("{}\n", to_json(rec)))
+            .collect(),
+        OutputFormat::Csv => {
+            let columns: Vec<_> = header.columns.iter().map(|c| c.as_str()).collect();
+            let mut csv = csv_row(&columns);
+            for record in records {
+                let fields = header.fields(record);
+                let fields: Vec<_> = fields.iter().map(|f| f.as_str()).collect();
+                csv.push_str(&csv_row(&fields));
+            }
+            csv
+        }
+    }
+}
+
+/// Formats records as a table with a column for each field that any of the
+/// records has. Each column is as wide as its longest value.
+fn format_table(records: &[&Record]) -> String {
+    let columns: Vec<_> = ["id", "name", "email", "phone", "address", "tags", "notes"]
+        .iter()
+        .filter(|column| records.iter().any(|rec| rec.field(column).is_some()))
+        .collect();
+    // Line breaks in addresses and notes would break the rows, so they are
+    // shown as " / " instead.
+    let rows: Vec<Vec<String>> = records
+        .iter()
+        .map(|rec| {
+            columns
+                .iter()
+                .map(|column| {
+                    let value = rec.field(column).unwrap_or_default();
+                    value.lines().collect::<Vec<_>>().join(" / ")
+                })
+                .collect()
+        })
+        .collect();
+    let mut widths: Vec<_> = columns.iter().map(|column| column.len()).collect();
+    for row in rows.iter() {
+        for (width, value) in widths.iter_mut().zip(row.iter()) {
+            // "len" counts bytes, so characters are counted instead to line
+            // up names with accents.
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    let format_row = |values: Vec<String>| -> String {
+        let cells: Vec<_> = values
+            .iter()
+            .zip(widths.iter())
+            .map(|(value, width)| format!("{:width$}", value, width = width))
+            .collect();
+        format!("{}\n", cells.join("  ").trim_end())
+    };
+    let mut table = format_row(columns.iter().map(|c| c.to_uppercase()).collect());
+    table.push_str(&format_row(widths.iter().map(|w| "-".repeat(*w)).collect()));
+    for row in rows {
+        table.push_str(&format_row(row));
+    }
+    table
+}
+
+/// Formats a string as a JSON string, escaping quotes, backslashes and
+/// control characters.
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Formats an optional string as JSON, using null when there is none.
+fn json_option(text: &Option<String>) -> String {
+    match text {
+        Some(text) => json_string(text),
+        None => "null".to_owned(),
+    }
+}
+
+/// Formats a record as a single line JSON object. Extra columns from the
+/// data file are included in an "extra" object.
+fn to_json(record: &Record) -> String {
+    let phones: Vec<_> = record
+        .phones
+        .iter()
+        .map(|phone| {
+            format!(
+                "{{\"label\":{},\"number\":{}}}",
+                json_option(&phone.label),
+                json_string(&phone.number)
+            )
+        })
+        .collect();
+    let tags: Vec<_> = record.tags.iter().map(|tag| json_string(tag)).collect();
+    let extra: Vec<_> = record
+        .extra
+        .iter()
+        .map(|(column, value)| format!("{}:{}", json_string(column), json_string(value)))
+        .collect();
+    format!(
+        "{{\"id\":{},\"name\":{},\"email\":{},\"phones\":[{}],\"address\":{},\"tags\":[{}],\"notes\":{},\"extra\":{{{}}}}}",
+        record.id,
+        json_string(&record.name),
+        json_option(&record.email),
+        phones.join(","),
+        json_option(&record.address),
+        tags.join(","),
+        json_option(&record.notes),
+        extra.join(",")
+    )
+}
+
+/// The vCard versions that can be exported. Both can be imported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VcardVersion {
+    V3,
+    V4,
+}
+
+impl std::str::FromStr for VcardVersion {
+    type Err = String;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        match version {
+            "3" | "3.0" => Ok(VcardVersion::V3),
+            "4" | "4.0" => Ok(VcardVersion::V4),
+            _ => Err(format!(
+                "unsupported vCard version {:?}, use 3.0 or 4.0",
+                version
+            )),
+        }
+    }
+}
+
+/// Errors that may occur while reading a vCard file. Cards are numbered
+/// from 1 in the order they appear in the file.
+#[derive(Error, Debug, PartialEq)]
+enum VcardError {
+    #[error("card {0} has no name")]
+    MissingName(usize),
+    #[error("card {0} has no END:VCARD line")]
+    MissingEnd(usize),
+}
+
+/// Lines longer than this many bytes are folded, as required by the
+/// vCard format.
+const VCARD_LINE_LENGTH: usize = 75;
+
+/// Escapes a text value so its commas, semicolons, backslashes and line
+/// breaks aren't confused with vCard syntax.
+fn vcard_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.replace("\r\n", "\n").chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            ',' => escaped.push_str("\\,"),
+            ';' => escaped.push_str("\\;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Splits a vCard value at every separator that isn't escaped, and unescapes
+/// the parts.
+fn vcard_split(value: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().expect("there is always a part");
+        match c {
+            '\\' => match chars.next() {
+                Some('n') | Some('N') => part.push('\n'),
+                Some(c) => part.push(c),
+                None => part.push('\\'),
+            },
+            c if c == separator => parts.push(String::new()),
+            c => part.push(c),
+        }
+    }
+    parts
+}
+
+/// Unescapes a vCard text value.
+fn vcard_unescape(value: &str) -> String {
+    // A line break can't appear in an unfolded value, so splitting on it
+    // unescapes the value without splitting it.
+    vcard_split(value, '\n').concat()
+}
+
+/// Folds a line so that no line is longer than `VCARD_LINE_LENGTH` bytes.
+/// Each continuation line starts with a space. Lines are only broken
+/// between characters, so multi-byte UTF-8 characters stay whole.
+fn vcard_fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > VCARD_LINE_LENGTH {
+            folded.push_str("\r\n ");
+            // The space at the start of the line counts towards its length.
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+/// Formats a parameter value, quoting it if it contains characters that
+/// have a meaning in a content line.
+fn vcard_param(value: &str) -> String {
+    if value.contains([',', ';', ':', ' ']) {
+        format!("\"{}\"", value.replace('"', ""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Formats a record as a vCard.
+fn to_vcard(record: &Record, version: VcardVersion) -> String {
+    let mut lines = vec!["BEGIN:VCARD".to_owned()];
+    lines.push(match version {
+        VcardVersion::V3 => "VERSION:3.0".to_owned(),
+        VcardVersion::V4 => "VERSION:4.0".to_owned(),
+    });
+    lines.push(format!("FN:{}", vcard_escape(&record.name)));
+    // vCard 3.0 requires the structured name too: family name, given names,
+    // additional names, prefixes and suffixes. The last word of the name is
+    // used as the family name.
+    let words: Vec<_> = record.name.split_whitespace().collect();
+    let (given, family) = match words.split_last() {
+        Some((last, rest)) if !rest.is_empty() => (rest.join(" "), last.to_string()),
+        _ => (record.name.clone(), String::new()),
+    };
+    lines.push(format!(
+        "N:{};{};;;",
+        vcard_escape(&family),
+        vcard_escape(&given)
+    ));
+    if let Some(email) = &record.email {
+        lines.push(match version {
+            VcardVersion::V3 => format!("EMAIL;TYPE=INTERNET:{}", vcard_escape(email)),
+            VcardVersion::V4 => format!("EMAIL:{}", vcard_escape(email)),
+        });
+    }
+    for phone in record.phones.iter() {
+        let number = vcard_escape(&phone.number);
+        lines.push(match &phone.label {
+            Some(label) => format!("TEL;TYPE={}:{}", vcard_param(label), number),
+            None => format!("TEL:{}", number),
+        });
+    }
+    if let Some(address) = &record.address {
+        // The address has seven parts: post office box, extended address,
+        // street, city, region, postal code and country. Our addresses
+        // are free-form, so all of it goes in the street part.
+        lines.push(format!("ADR:;;{};;;;", vcard_escape(address)));
+    }
+    if let Some(notes) = &record.notes {
+        lines.push(format!("NOTE:{}", vcard_escape(notes)));
+    }
+    if !record.tags.is_empty() {
+        let tags: Vec<_> = record.tags.iter().map(|tag| vcard_escape(tag)).collect();
+        lines.push(format!("CATEGORIES:{}", tags.join(",")));
+    }
+    lines.push("END:VCARD".to_owned());
+    lines.iter().map(|line| vcard_fold(line)).collect()
+}
+
+/// A single unfolded line of a vCard, like `TEL;TYPE=work:555-0100`.
+#[derive(Debug)]
+struct ContentLine {
+    /// The uppercase property name, without any group prefix.
+    name: String,
+    /// The parameters, with uppercase names. Parameters without a name,
+    /// as in vCard 2.1 (`TEL;WORK:...`), are treated as TYPE values.
+    params: Vec<(String, String)>,
+    /// The raw value, still escaped.
+    value: String,
+}
+
+impl ContentLine {
+    /// Parses a content line. Returns None if it has no value.
+    fn parse(line: &str) -> Option<Self> {
+        // The value starts at the first colon that isn't inside a quoted
+        // parameter value.
+        let mut in_quotes = false;
+        let colon = line.char_indices().find_map(|(i, c)| match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                None
+            }
+            ':' if !in_quotes => Some(i),
+            _ => None,
+        })?;
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+
+        let mut parts = head.split(';');
+        let name = parts.next().unwrap_or_default();
+        // "item1.EMAIL" is an EMAIL property in group "item1".
+        let name = name.rsplit('.').next().unwrap_or(name).to_uppercase();
+        let mut params = vec![];
+        for param in parts {
+            let (key, values) = match param.split_once('=') {
+                Some((key, values)) => (key.to_uppercase(), values),
+                None => ("TYPE".to_owned(), param),
+            };
+            for value in values.split(',') {
+                params.push((key.clone(), value.trim_matches('"').to_string()));
+            }
+        }
+        Some(Self {
+            name,
+            params,
+            value: value.to_string(),
+        })
+    }
+
+    /// Returns the TYPE parameter values in lowercase.
+    fn types(&self) -> Vec<String> {
+        self.params
+            .iter()
+            .filter(|(key, _)| key == "TYPE")
+            .map(|(_, value)| value.to_lowercase())
+            .collect()
+    }
+
+    /// Checks whether the property is marked as preferred, which is a TYPE
+    /// in vCard 3.0 and a PREF parameter in vCard 4.0.
+    fn is_preferred(&self) -> bool {
+        self.types().iter().any(|t| t == "pref") || self.params.iter().any(|(key, _)| key == "PREF")
+    }
+}
+
+/// Reads every card in a vCard file. Each card becomes a record with an id
+/// of 0, since ids are only given out when records are added.
+fn parse_vcards(data: &str) -> Vec<Result<Record, VcardError>> {
+    let data = data.strip_prefix('\u{feff}').unwrap_or(data);
+    // Long lines are folded by breaking them and starting the rest with a
+    // space or tab, so those lines are joined back first.
+    let mut lines: Vec<String> = vec![];
+    for line in data.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut cards = vec![];
+    let mut card: Option<Vec<ContentLine>> = None;
+    for line in lines.iter() {
+        let content = match ContentLine::parse(line) {
+            Some(content) => content,
+            None => continue,
+        };
+        let value = content.value.to_uppercase();
+        if content.name == "BEGIN" && value == "VCARD" {
+            if card.is_some() {
+                cards.push(Err(VcardError::MissingEnd(cards.len() + 1)));
+            }
+            card = Some(vec![]);
+        } else if content.name == "END" && value == "VCARD" {
+            if let Some(lines) = card.take() {
+                cards.push(vcard_to_record(&lines, cards.len() + 1));
+            }
+        } else if let Some(lines) = card.as_mut() {
+            lines.push(content);
+        }
+    }
+    if card.is_some() {
+        cards.push(Err(VcardError::MissingEnd(cards.len() + 1)));
+    }
+    cards
+}
+
+/// Converts the properties of a card into a record.
+fn vcard_to_record(lines: &[ContentLine], number: usize) -> Result<Record, VcardError> {
+    let first = |name: &str| lines.iter().find(|line| line.name == name);
+
+    // The formatted name is used when there is one, otherwise the name is
+    // put together from the structured name.
+    let name = match first("FN") {
+        Some(line) => vcard_unescape(&line.value),
+        None => match first("N") {
+            Some(line) => {
+                let parts = vcard_split(&line.value, ';');
+                let order = [3, 1, 2, 0, 4];
+                let words: Vec<_> = order
+                    .iter()
+                    .filter_map(|&i| parts.get(i))
+                    .map(|part| part.trim())
+                    .filter(|part| !part.is_empty())
+                    .collect();
+                words.join(" ")
+            }
+            None => String::new(),
+        },
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(VcardError::MissingName(number));
+    }
+    let mut record = Record::new(0, name, None);
+
+    // A record has one email, so the preferred one is used. Any others are
+    // kept in the notes so they aren't lost.
+    let mut emails: Vec<_> = lines.iter().filter(|line| line.name == "EMAIL").collect();
+    emails.sort_by_key(|line| !line.is_preferred());
+    let mut emails = emails
+        .iter()
+        .map(|line| vcard_unescape(&line.value).trim().to_string())
+        .filter(|email| !email.is_empty());
+    record.email = emails.next();
+    let mut notes: Vec<String> = emails
+        .map(|email| format!("Other email: {}", email))
+        .collect();
+
+    for line in lines.iter() {
+        let value = vcard_unescape(&line.value);
+        match line.name.as_str() {
+            "TEL" => {
+                // vCard 4.0 may write numbers as "tel:" links. Semicolons
+                // and colons would be confused with our own separators.
+                let number = value
+                    .trim()
+                    .trim_start_matches("tel:")
+                    .replace(LIST_SEPARATOR, " ");
+                // The first TYPE that says what the number is for is used
+                // as the label. The others only describe the kind of line.
+                let generic = ["voice", "pref", "text", "msg", "internet"];
+                let label = line
+                    .types()
+                    .into_iter()
+                    .find(|t| !generic.contains(&t.as_str()))
+                    .map(|t| t.replace([':', LIST_SEPARATOR], " "));
+                if !number.is_empty() {
+                    record.phones.push(Phone { label, number });
+                }
+            }
+            "ADR" => {
+                let parts: Vec<_> = vcard_split(&line.value, ';')
+                    .into_iter()
+                    .map(|part| part.trim().to_string())
+                    .filter(|part| !part.is_empty())
+                    .collect();
+                if record.address.is_none() && !parts.is_empty() {
+                    record.address = Some(parts.join("\n"));
+                }
+            }
+            "NOTE" if !value.trim().is_empty() => notes.insert(0, value.trim().to_string()),
+            "CATEGORIES" => {
+                let tags = vcard_split(&line.value, ',')
+                    .into_iter()
+                    .map(|tag| tag.trim().replace(LIST_SEPARATOR, " "))
+                    .filter(|tag| !tag.is_empty());
+                record.tags.extend(tags);
+            }
+            _ => {}
+        }
+    }
+    record.tags = dedup_tags(std::mem::take(&mut record.tags));
+    if !notes.is_empty() {
+        record.notes = Some(notes.join("\n"));
+    }
+    Ok(record)
+}
+
+/// The fields of a contact that can be changed in the edit form, in the
+/// order they are shown.
+const FORM_FIELDS: [&str; 6] = ["name", "email", "phone", "address", "tags", "notes"];
+
+/// The form used to add or edit a contact in the interactive mode.
+#[derive(Debug)]
+struct Form {
+    /// The record being edited, or None when adding a new one.
+    id: Option<i64>,
+    /// The text of each of `FORM_FIELDS`. Phones and tags are separated by
+    /// semicolons, just like in the data file.
+    values: Vec<String>,
+    /// The field being typed in.
+    focus: usize,
+}
+
+impl Form {
+    /// Creates a form filled in with the details of a record.
+    fn new(record: Option<&Record>) -> Self {
+        let values = FORM_FIELDS
+            .iter()
+            .map(|column| {
+                record
+                    .and_then(|record| record.field(column))
+                    .unwrap_or_default()
+            })
+            .collect();
+        Self {
+            id: record.map(|record| record.id),
+            values,
+            focus: 0,
+        }
+    }
+
+    /// Creates a record from the form. The values are read with
+    /// "parse_record", as if they were the fields of a row in the data
+    /// file, so they are checked the same way.
+    fn record(&self, id: i64) -> Result<Record, ParseError> {
+        let mut columns = vec!["id".to_owned()];
+        columns.extend(FORM_FIELDS.iter().map(|column| column.to_string()));
+        let mut fields = vec![id.to_string()];
+        fields.extend(self.values.iter().map(|value| value.trim().to_string()));
+        let record = parse_record(&Header { columns }, &fields)?;
+        match validate_record(&record).into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(record),
+        }
+    }
+}
+
+/// What the keys do in the interactive mode.
+#[derive(Debug)]
+enum Mode {
+    /// Moving through the list.
+    Browse,
+    /// Typing a search query.
+    Search,
+    /// Filling in the form to add or edit a contact.
+    Form(Form),
+    /// Waiting for the removal of a contact to be confirmed.
+    ConfirmRemove(i64),
+}
+
+/// The state of the interactive mode.
+///
+/// Changes are made with the same `Records` methods as the commands, and
+/// are saved right away, just like the commands save them.
+struct App {
+    storage: Box<dyn Storage>,
+    records: Records,
+    /// The order of the list when there is no search query.
+    sort: SortKey,
+    query: String,
+    /// The ids of the records in the list, in the order they are shown.
+    shown: Vec<i64>,
+    /// The selected row of the list, along with how far it is scrolled.
+    list: ListState,
+    mode: Mode,
+    /// A message about the last thing that happened, like an error.
+    status: String,
+}
+
+impl App {
+    /// Creates the interactive mode, showing every record sorted by id.
+    fn new(storage: Box<dyn Storage>, records: Records) -> Self {
+        let mut app = Self {
+            storage,
+            records,
+            sort: SortKey::Id,
+            query: String::new(),
+            shown: vec![],
+            list: ListState::default(),
+            mode: Mode::Browse,
+            status: String::new(),
+        };
+        app.refresh();
+        app
+    }
+
+    /// Returns the selected record.
+    fn selected(&self) -> Option<&Record> {
+        let id = self.list.selected().and_then(|i| self.shown.get(i))?;
+        self.records.inner.get(id)
+    }
+
+    /// Selects the record with the given id, if it is in the list.
+    fn select(&mut self, id: i64) {
+        if let Some(i) = self.shown.iter().position(|&shown| shown == id) {
+            self.list.select(Some(i));
+        }
+    }
+
+    /// Moves the selection up (negative) or down (positive) the list,
+    /// stopping at the first and last records.
+    fn move_selection(&mut self, rows: isize) {
+        if self.shown.is_empty() {
+            return;
+        }
+        let current = self.list.selected().unwrap_or(0) as isize;
+        let last = self.shown.len() as isize - 1;
+        self.list
+            .select(Some((current + rows).clamp(0, last) as usize));
+    }
+
+    /// Works out which records are shown after the records, the search
+    /// query or the sort order changed. Search results are shown with the
+    /// best matches first, like the search command does.
+    fn refresh(&mut self) {
+        let selected = self.selected().map(|record| record.id);
+        self.shown = if self.query.trim().is_empty() {
+            let options = ListOptions {
+                sort: self.sort,
+                ..ListOptions::default()
+            };
+            let records = self.records.list(&options);
+            records.iter().map(|record| record.id).collect()
+        } else {
+            let results = self
+                .query
+                .parse::<Query>()
+                .and_then(|query| self.records.search(&query));
+            match results {
+                Ok(results) => results.iter().map(|record| record.id).collect(),
+                Err(e) => {
+                    self.status = format!("invalid query: {}", e);
+                    vec![]
+                }
+            }
+        };
+        // The list starts out scrolled to the top again, since the rows it
+        // was scrolled past may not be the same ones. The same record stays
+        // selected if it is still in the list.
+        let row = self.list.selected().unwrap_or(0);
+        self.list = ListState::default();
+        self.list.select(match self.shown.len() {
+            0 => None,
+            len => Some(row.min(len - 1)),
+        });
+        if let Some(id) = selected {
+            self.select(id);
+        }
+    }
+
+    /// Saves the changes, then loads the records again so that changes made
+    /// by other programs in the meantime show up too. An error while saving
+    /// is shown in the status line, since it shouldn't end the program.
+    /// Returns whether the changes were saved.
+    fn save(&mut self, changed: &[i64], removed: &[i64]) -> std::io::Result<bool> {
+        let records = std::mem::replace(&mut self.records, Records::new());
+        let saved = match self.storage.update(records, changed, removed) {
+            Ok(()) => true,
+            Err(e) => {
+                self.status = format!("an error occurred: {}", e);
+                false
+            }
+        };
+        self.records = self.storage.load(false)?;
+        self.refresh();
+        Ok(saved)
+    }
+
+    /// Handles a key press. Returns true when the program should quit.
+    fn handle_key(&mut self, key: KeyEvent) -> std::io::Result<bool> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Ok(true);
+        }
+        // The message is only shown until the next key is pressed.
+        self.status.clear();
+        // The mode is taken out so that the form can be changed while the
+        // records are, and each mode puts back the one that comes next.
+        match std::mem::replace(&mut self.mode, Mode::Browse) {
+            Mode::Browse => return self.browse_key(key.code),
+            Mode::Search => self.search_key(key.code),
+            Mode::Form(form) => self.form_key(form, key.code)?,
+            Mode::ConfirmRemove(id) => {
+                if key.code == KeyCode::Char('y') {
+                    // "remove" is what the remove command uses too.
+                    if self.records.remove(id).is_some() && self.save(&[], &[id])? {
+                        self.status = "record deleted".to_owned();
+                    }
+                } else {
+                    self.status = "nothing was deleted".to_owned();
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Handles a key while moving through the list.
+    fn browse_key(&mut self, key: KeyCode) -> std::io::Result<bool> {
+        match key {
+            KeyCode::Char('q') => return Ok(true),
+            // Escape clears the search first, and quits after that.
+            KeyCode::Esc if !self.query.is_empty() => {
+                self.query.clear();
+                self.refresh();
+            }
+            KeyCode::Esc => return Ok(true),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-10),
+            KeyCode::PageDown => self.move_selection(10),
+            KeyCode::Home => self.move_selection(-(self.shown.len() as isize)),
+            KeyCode::End => self.move_selection(self.shown.len() as isize),
+            KeyCode::Char('/') => self.mode = Mode::Search,
+            KeyCode::Char('s') => {
+                self.sort = match self.sort {
+                    SortKey::Id => SortKey::Name,
+                    _ => SortKey::Id,
+                };
+                self.refresh();
+            }
+            KeyCode::Char('a') => self.mode = Mode::Form(Form::new(None)),
+            KeyCode::Char('e') | KeyCode::Enter => {
+                if let Some(record) = self.selected() {
+                    self.mode = Mode::Form(Form::new(Some(record)));
+                }
+            }
+            KeyCode::Char('d') | KeyCode::Delete => {
+                if let Some(record) = self.selected() {
+                    self.mode = Mode::ConfirmRemove(record.id);
+                }
+            }
+            _ => (),
+        }
+        Ok(false)
+    }
+
+    /// Handles a key while typing a search query. The list is updated after
+    /// every key.
+    fn search_key(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::Char(c) => {
+                self.query.push(c);
+                self.refresh();
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.refresh();
+            }
+            KeyCode::Up => self.move_selection(-1),
+            KeyCode::Down => self.move_selection(1),
+            // Enter keeps the results, and escape goes back to every record.
+            KeyCode::Enter => return,
+            KeyCode::Esc => {
+                self.query.clear();
+                self.refresh();
+                return;
+            }
+            _ => (),
+        }
+        self.mode = Mode::Search;
+    }
+
+    /// Handles a key while filling in the form.
+    fn form_key(&mut self, mut form: Form, key: KeyCode) -> std::io::Result<()> {
+        match key {
+            KeyCode::Esc => return Ok(()),
+            KeyCode::Enter => return self.submit(form),
+            KeyCode::Tab | KeyCode::Down => form.focus = (form.focus + 1) % FORM_FIELDS.len(),
+            KeyCode::BackTab | KeyCode::Up => {
+                form.focus = (form.focus + FORM_FIELDS.len() - 1) % FORM_FIELDS.len()
+            }
+            KeyCode::Char(c) => form.values[form.focus].push(c),
+            KeyCode::Backspace => {
+                form.values[form.focus].pop();
+            }
+            _ => (),
+        }
+        self.mode = Mode::Form(form);
+        Ok(())
+    }
+
+    /// Adds or edits the record from the form. When the form has a problem,
+    /// or the record couldn't be saved, it stays open so that it can be fixed
+    /// or tried again.
+    fn submit(&mut self, form: Form) -> std::io::Result<()> {
+        let id = form.id.unwrap_or_else(|| self.records.next_id());
+        let record = match form.record(id) {
+            Ok(record) => record,
+            Err(e) => {
+                self.status = e.to_string();
+                self.mode = Mode::Form(form);
+                return Ok(());
+            }
+        };
+        // These are the same steps as the add and edit commands take, so
+        // columns the form doesn't show are kept when editing.
+        if form.id.is_some() {
+            self.records.edit(id, &record.name, record.email);
+            if let Some(existing) = self.records.get_mut(id) {
+                existing.phones = record.phones;
+                existing.address = record.address;
+                existing.tags = record.tags;
+                existing.notes = record.notes;
+            }
+        } else {
+            self.records.add(record);
+        }
+        if !self.save(&[id], &[])? {
+            self.mode = Mode::Form(form);
+            return Ok(());
+        }
+        self.select(id);
+        self.status = "record saved".to_owned();
+        Ok(())
+    }
+}
+
+/// Draws the interactive mode: the search query at the top, the list of
+/// records, and a status line with the keys that can be used.
+fn draw<B: tui::backend::Backend>(f: &mut Frame<B>, app: &mut App) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .split(f.size());
+
+    let search = Paragraph::new(app.query.as_str())
+        .block(Block::default().borders(Borders::ALL).title("search"));
+    f.render_widget(search, chunks[0]);
+    if let Mode::Search = app.mode {
+        let x = chunks[0].x + 1 + app.query.chars().count() as u16;
+        f.set_cursor(x.min(chunks[0].right().saturating_sub(2)), chunks[0].y + 1);
+    }
+
+    let items: Vec<_> = app
+        .shown
+        .iter()
+        .filter_map(|id| app.records.inner.get(id))
+        .map(|record| ListItem::new(record.to_string()))
+        .collect();
+    let order = match (app.query.trim().is_empty(), app.sort) {
+        (false, _) => "best matches first",
+        (true, SortKey::Name) => "sorted by name",
+        (true, _) => "sorted by id",
+    };
+    let title = format!("{} contact(s), {}", items.len(), order);
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    f.render_stateful_widget(list, chunks[1], &mut app.list);
+
+    let status = if !app.status.is_empty() {
+        app.status.clone()
+    } else {
+        match &app.mode {
+            Mode::Browse => "/ search  s sort  a add  e edit  d delete  q quit".to_owned(),
+            Mode::Search => "type to search  enter keep results  esc clear".to_owned(),
+            Mode::Form(_) => "tab next field  enter save  esc cancel".to_owned(),
+            Mode::ConfirmRemove(id) => match app.records.inner.get(id) {
+                Some(record) => format!("delete {}? y/n", record),
+                None => "delete? y/n".to_owned(),
+            },
+        }
+    };
+    f.render_widget(Paragraph::new(status), chunks[2]);
+
+    if let Mode::Form(form) = &app.mode {
+        draw_form(f, form, chunks[1]);
+    }
+}
+
+/// Draws the form over the middle of the list.
+fn draw_form<B: tui::backend::Backend>(f: &mut Frame<B>, form: &Form, area: Rect) {
+    let height = (FORM_FIELDS.len() as u16 + 2).min(area.height);
+    let width = area.width.saturating_sub(4).min(70);
+    let area = Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    );
+    let label_width = FORM_FIELDS.iter().map(|f| f.len()).max().unwrap_or(0) + 2;
+    let lines: Vec<_> = FORM_FIELDS
+        .iter()
+        .zip(form.values.iter())
+        .enumerate()
+        .map(|(i, (label, value))| {
+            let label = format!("{:width$}", format!("{}:", label), width = label_width);
+            let style = if i == form.focus {
+                Style::default().add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            Spans::from(vec![Span::styled(label, style), Span::raw(value.as_str())])
+        })
+        .collect();
+    let title = match form.id {
+        Some(id) => format!("edit contact {}", id),
+        None => "add contact".to_owned(),
+    };
+    // "Clear" blanks out the list behind the form.
+    f.render_widget(Clear, area);
+    f.render_widget(
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+        area,
+    );
+    let x = area.x + 1 + (label_width + form.values[form.focus].chars().count()) as u16;
+    f.set_cursor(
+        x.min(area.right().saturating_sub(2)),
+        area.y + 1 + form.focus as u16,
+    );
+}
+
+/// Runs the interactive mode until it is quit.
+fn run_tui(storage: Box<dyn Storage>, verbose: bool) -> std::io::Result<()> {
+    let records = storage.load(verbose)?;
+    let mut app = App::new(storage, records);
+
+    // In "raw mode" keys are sent to the program right away, instead of
+    // after enter is pressed, and the alternate screen keeps the contents
+    // of the terminal so they come back when the program ends.
+    terminal::enable_raw_mode()?;
+    let mut stdout = std::io::stdout();
+    crossterm::execute!(stdout, terminal::EnterAlternateScreen)?;
+    let mut term = Terminal::new(CrosstermBackend::new(stdout))?;
+
+    // The terminal is put back to normal even after an error, otherwise
+    // the shell would be left in raw mode.
+    let result = tui_loop(&mut term, &mut app);
+    terminal::disable_raw_mode()?;
+    crossterm::execute!(term.backend_mut(), terminal::LeaveAlternateScreen)?;
+    term.show_cursor()?;
+    result
+}
+
+/// Draws the screen and handles keys until the program should quit.
+fn tui_loop<B: tui::backend::Backend>(
+    term: &mut Terminal<B>,
+    app: &mut App,
+) -> std::io::Result<()> {
+    loop {
+        term.draw(|f| draw(f, app))?;
+        if let Event::Key(key) = event::read()? {
+            if app.handle_key(key)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "project 2: contact manager")]
+struct Opt {
+    #[structopt(short, parse(from_os_str), default_value = "p2_data.csv")]
+    data_file: PathBuf,
+    #[structopt(subcommand)]
+    cmd: Command,
+    #[structopt(short, help = "verbose")]
+    verbose: bool,
+    /// How the data file is stored: csv or sqlite. By default ".db", ".sqlite"
+    /// and ".sqlite3" files are SQLite databases and other files are CSV
+    #[structopt(short, long)]
+    backend: Option<Backend>,
+}
+
+/// Optional contact details that can be given to the add and edit commands.
+#[derive(StructOpt, Debug)]
+struct Details {
+    /// Phone number, optionally with a label like "work:555-0100". Can be repeated
+    #[structopt(
+        short,
+        long = "phone",
+        number_of_values = 1,
+        parse(try_from_str = parse_phone)
+    )]
+    phones: Vec<Phone>,
+    /// Postal address
+    #[structopt(short, long)]
+    address: Option<String>,
+    /// Tag used to group contacts. Can be repeated
+    #[structopt(short, long = "tag", number_of_values = 1, parse(try_from_str = parse_tag))]
+    tags: Vec<String>,
+    /// Notes about the contact
+    #[structopt(short, long)]
+    notes: Option<String>,
+}
+
+impl Details {
+    /// Sets the details that were given on a record. Phones and tags replace
+    /// the existing ones, and an empty address or notes removes them.
+    fn apply(self, record: &mut Record) {
+        if !self.phones.is_empty() {
+            record.phones = self.phones;
+        }
+        if let Some(address) = self.address {
+            record.address = Some(address).filter(|a| !a.is_empty());
+        }
+        if !self.tags.is_empty() {
+            record.tags = dedup_tags(self.tags);
+        }
+        if let Some(notes) = self.notes {
+            record.notes = Some(notes).filter(|n| !n.is_empty());
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    Add {
+        name: String,
+        #[structopt(short, parse(try_from_str = parse_email))]
+        email: Option<String>,
+        #[structopt(flatten)]
+        details: Details,
+    },
+    /// Change a contact. Details that aren't given are left as they are
+    Edit {
+        id: i64,
+        name: String,
+        /// New email. An empty email removes it
+        #[structopt(short, parse(try_from_str = parse_email))]
         email: Option<String>,
+        #[structopt(flatten)]
+        details: Details,
+        /// Remove the phones or tags of the contact
+        #[structopt(long, possible_values = &["phones", "tags"])]
+        clear: Vec<String>,
+    },
+    /// List the contacts, sorted by id unless another order is given
+    List {
+        #[structopt(flatten)]
+        options: ListOptions,
+        /// How to show the contacts: table, json, jsonl or csv
+        #[structopt(short, long, default_value = "table")]
+        format: OutputFormat,
     },
-    List {},
     Remove {
         id: i64,
     },
+    /// Search for contacts. Each word has to match a field of the contact,
+    /// with small typos allowed. Use "field:word" to search a single field,
+    /// as in "email:@msu.edu name:val". The best matches are shown first
     Search {
-        query: String,
+        #[structopt(required = true)]
+        query: Vec<String>,
+        /// Only show contacts with this tag
+        #[structopt(short, long)]
+        tag: Option<String>,
+        /// Sort by id, name or email instead of by how well contacts match
+        #[structopt(short, long)]
+        sort: Option<SortKey>,
+        /// Show at most this many contacts
+        #[structopt(short, long)]
+        limit: Option<usize>,
+        /// How to show the contacts: table, json, jsonl or csv
+        #[structopt(short, long, default_value = "table")]
+        format: OutputFormat,
+    },
+    /// Check the data file for problems. Prints a CSV report with the line
+    /// (or contact id for a database), field and error of each problem, and
+    /// exits with status 1 if any are found
+    Validate {},
+    /// Find contacts that look like duplicates: the same email or a similar
+    /// name. Shows numbered groups, which can then be merged into the contact
+    /// with the lowest id
+    Dedupe {
+        /// Merge this group. Can be repeated
+        #[structopt(short, long, number_of_values = 1)]
+        merge: Vec<usize>,
+        /// Merge every group
+        #[structopt(short, long, conflicts_with = "merge")]
+        all: bool,
+        /// How similar names must be to count as duplicates, from 0 to 1
+        #[structopt(long, default_value = "0.85")]
+        threshold: f64,
+    },
+    /// Roll the data file back to a backup. Every save keeps a backup of the
+    /// previous contents. Lists the backups when no backup is given
+    Restore {
+        /// Number of the backup in the list (1 is the newest), or its file name
+        backup: Option<String>,
+    },
+    /// Add the contacts from a vCard (.vcf) file. Version 3.0 and 4.0 files
+    /// with any number of contacts are supported
+    Import {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
     },
+    /// Write the contacts to a vCard (.vcf) file, or to the screen if the
+    /// file is "-"
+    Export {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// The vCard version to write: 3.0 or 4.0
+        #[structopt(long, default_value = "3.0")]
+        vcard_version: VcardVersion,
+        /// Only export contacts with this tag
+        #[structopt(short, long)]
+        tag: Option<String>,
+    },
+    /// Copy the contacts to a new data file, which may use another backend.
+    /// "migrate p2_data.db" copies them from the CSV file into an SQLite database
+    Migrate {
+        #[structopt(parse(from_os_str))]
+        to: PathBuf,
+        /// The backend of the new data file: csv or sqlite. By default it is
+        /// picked from the file extension
+        #[structopt(long)]
+        to_backend: Option<Backend>,
+        /// Replace the contacts of the new data file if it already exists
+        #[structopt(long)]
+        force: bool,
+    },
+    /// Browse the contacts in a full-screen view, with a search that updates
+    /// as you type. Contacts can be added, edited and deleted from there too
+    Tui {},
+}
+
+/// Options that choose which contacts are listed and in what order.
+#[derive(StructOpt, Debug, Default)]
+struct ListOptions {
+    /// Only list contacts with this tag
+    #[structopt(short, long)]
+    tag: Option<String>,
+    /// Sort by id, name or email
+    #[structopt(short, long, default_value = "id")]
+    sort: SortKey,
+    /// List the contacts in the opposite order
+    #[structopt(short, long)]
+    reverse: bool,
+    /// Skip this many contacts first, to show the later pages of a long list
+    #[structopt(short, long, default_value = "0")]
+    offset: usize,
+    /// List at most this many contacts
+    #[structopt(short, long)]
+    limit: Option<usize>,
+    /// Only list contacts that have an email
+    #[structopt(long, conflicts_with = "missing-email")]
+    has_email: bool,
+    /// Only list contacts without an email
+    #[structopt(long)]
+    missing_email: bool,
+}
+
+impl ListOptions {
+    /// Checks whether a record passes the filters.
+    fn matches(&self, record: &Record) -> bool {
+        tag_matches(record, &self.tag)
+            && !(self.has_email && record.email.is_none())
+            && !(self.missing_email && record.email.is_some())
+    }
+}
+
+/// Checks whether a record has the tag used to filter a command, if any.
+fn tag_matches(record: &Record, tag: &Option<String>) -> bool {
+    match tag {
+        Some(tag) => record.has_tag(tag),
+        None => true,
+    }
 }
 
 /// Runs the program. This is so we can utilize the question mark operator.
 fn run(opt: Opt) -> Result<(), std::io::Error> {
+    // The storage is only opened by the commands that use it, since opening
+    // an SQLite database creates it.
+    let data_file = opt.data_file.clone();
+    let backend = opt
+        .backend
+        .unwrap_or_else(|| Backend::for_file(&opt.data_file));
+    let open = || open_storage(&data_file, Some(backend));
+    // Commands that only read the contacts fail when the data file is
+    // missing, rather than creating an empty database for a mistyped name.
+    let open_existing = || {
+        if !data_file.exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} doesn't exist", data_file.display()),
+            ));
+        }
+        open()
+    };
     match opt.cmd {
-        Command::Add { name, email } => {
-            let mut recs = load_records(opt.data_file.clone(), opt.verbose)?;
+        Command::Add {
+            name,
+            email,
+            details,
+        } => {
+            let mut storage = open()?;
+            let mut recs = storage.load(opt.verbose)?;
             let next_id = recs.next_id();
-            recs.add(Record {
-                id: next_id,
-                name,
-                email,
-            });
-            save_records(opt.data_file, recs)?;
+            let mut record = Record::new(next_id, &name, email.filter(|e| !e.is_empty()));
+            details.apply(&mut record);
+            // The same checks as the form makes, so that a contact which
+            // couldn't be loaded again is never saved.
+            if let Some(error) = validate_record(&record).into_iter().next() {
+                println!("invalid contact: {}", error);
+                return Ok(());
+            }
+            recs.add(record);
+            storage.update(recs, &[next_id], &[])?;
         }
-        Command::Edit { id, name, email } => {
-            let mut recs = load_records(opt.data_file.clone(), opt.verbose)?;
+        Command::Edit {
+            id,
+            name,
+            email,
+            details,
+            clear,
+        } => {
+            let mut storage = open()?;
+            let mut recs = storage.load(opt.verbose)?;
+            // Without an email on the command line the current one is kept.
+            let email = match email {
+                Some(email) => Some(email).filter(|e| !e.is_empty()),
+                None => recs.get_mut(id).and_then(|rec| rec.email.take()),
+            };
             recs.edit(id, &name, email);
-            save_records(opt.data_file, recs)?;
-        }
-        Command::List { .. } => {
-            let recs = load_records(opt.data_file, opt.verbose)?;
-            for record in recs.into_vec() {
-                println!("{:?}", record);
+            if let Some(record) = recs.get_mut(id) {
+                if clear.iter().any(|c| c == "phones") {
+                    record.phones.clear();
+                }
+                if clear.iter().any(|c| c == "tags") {
+                    record.tags.clear();
+                }
+                details.apply(record);
+                if let Some(error) = validate_record(record).into_iter().next() {
+                    println!("invalid contact: {}", error);
+                    return Ok(());
+                }
             }
+            storage.update(recs, &[id], &[])?;
+        }
+        Command::List { options, format } => {
+            let recs = open_existing()?.load(opt.verbose)?;
+            let records = recs.list(&options);
+            let header = recs.header_for_saving();
+            print!("{}", format_records(&records, format, &header));
         }
         Command::Remove { id } => {
-            let mut recs = load_records(opt.data_file.clone(), opt.verbose)?;
+            let mut storage = open()?;
+            let mut recs = storage.load(opt.verbose)?;
             if recs.remove(id).is_some() {
-                save_records(opt.data_file, recs)?;
+                storage.update(recs, &[], &[id])?;
                 println!("record deleted");
             } else {
                 println!("record not found");
             }
         }
-        Command::Search { query } => {
-            let recs = load_records(opt.data_file, opt.verbose)?;
-            let results = recs.search(&query);
-            if results.is_empty() {
+        Command::Search {
+            query,
+            tag,
+            sort,
+            limit,
+            format,
+        } => {
+            // The words of the query may be given as separate arguments.
+            let query = match query.join(" ").parse::<Query>() {
+                Ok(query) => query,
+                Err(e) => {
+                    println!("invalid query: {}", e);
+                    return Ok(());
+                }
+            };
+            let recs = open_existing()?.load(opt.verbose)?;
+            let mut results = match recs.search(&query) {
+                Ok(results) => results,
+                Err(e) => {
+                    println!("invalid query: {}", e);
+                    return Ok(());
+                }
+            };
+            results.retain(|rec| tag_matches(rec, &tag));
+            if let Some(key) = sort {
+                results.sort_by(|a, b| key.compare(a, b));
+            }
+            if let Some(limit) = limit {
+                results.truncate(limit);
+            }
+            // The message is only for people. Other formats print an empty
+            // list, which is easier for programs to handle.
+            if results.is_empty() && format == OutputFormat::Table {
                 println!("no records found");
             } else {
-                for rec in results {
-                    println!("{:?}", rec);
+                let header = recs.header_for_saving();
+                print!("{}", format_records(&results, format, &header));
+            }
+        }
+        Command::Validate {} => {
+            // The first column is the line for CSV files and the contact id
+            // for databases, which don't have lines.
+            let (column, problems): (_, Vec<_>) = match backend {
+                Backend::Csv => {
+                    let problems = validate(&read_file(opt.data_file)?);
+                    let problems = problems.into_iter();
+                    (
+                        "line",
+                        problems
+                            .map(|(line, f, e)| (line.to_string(), f, e))
+                            .collect(),
+                    )
+                }
+                Backend::Sqlite => {
+                    let recs = open_existing()?.load(opt.verbose)?;
+                    let problems = validate_records(&recs).into_iter();
+                    (
+                        "id",
+                        problems.map(|(id, f, e)| (id.to_string(), f, e)).collect(),
+                    )
+                }
+            };
+            // The report is CSV so that it can be read by other programs.
+            print!("{}", csv_row(&[column, "field", "error"]));
+            for (first, field, error) in problems.iter() {
+                print!("{}", csv_row(&[first, field, error]));
+            }
+            if !problems.is_empty() {
+                eprintln!("found {} problem(s)", problems.len());
+                std::process::exit(1);
+            }
+        }
+        Command::Dedupe {
+            merge,
+            all,
+            threshold,
+        } => {
+            if !(0.0..=1.0).contains(&threshold) {
+                println!("the threshold must be between 0 and 1");
+                return Ok(());
+            }
+            let mut storage = open_existing()?;
+            let mut recs = storage.load(opt.verbose)?;
+            let groups = recs.duplicates(threshold);
+            if groups.is_empty() {
+                println!("no duplicates found");
+                return Ok(());
+            }
+            // Groups are numbered from 1 so they can be picked with --merge.
+            for (num, group) in groups.iter().enumerate() {
+                println!("group {}:", num + 1);
+                for id in group {
+                    println!("  {}", recs.inner[id]);
+                }
+            }
+
+            let selected: Vec<usize> = if all {
+                (1..=groups.len()).collect()
+            } else {
+                merge
+            };
+            if selected.is_empty() {
+                return Ok(());
+            }
+            // Check every group number before merging anything, so a typo
+            // doesn't leave the merge half done.
+            if let Some(num) = selected.iter().find(|&&n| n == 0 || n > groups.len()) {
+                println!("there is no group {}", num);
+                return Ok(());
+            }
+            let (mut changed, mut removed) = (vec![], vec![]);
+            for num in selected {
+                let group = &groups[num - 1];
+                if let Some(id) = recs.merge(group) {
+                    println!("merged group {} into {}", num, recs.inner[&id]);
+                    changed.push(id);
+                    removed.extend(group.iter().filter(|&&other| other != id));
+                }
+            }
+            storage.update(recs, &changed, &removed)?;
+        }
+        Command::Restore { backup } => {
+            // Backups are made when a CSV file is written again. SQLite
+            // databases are changed in place, so they have none.
+            if backend == Backend::Sqlite {
+                println!("only CSV data files have backups");
+                return Ok(());
+            }
+            let backups = list_backups(&opt.data_file)?;
+            let backup = match backup {
+                Some(backup) => backup,
+                None => {
+                    if backups.is_empty() {
+                        println!("no backups found");
+                    }
+                    for (num, backup) in backups.iter().enumerate() {
+                        println!("{}: {}", num + 1, backup.display());
+                    }
+                    return Ok(());
+                }
+            };
+            // The backup can be picked by its number in the list, or by
+            // its file name.
+            let chosen = match backup.parse::<usize>() {
+                Ok(num) => num.checked_sub(1).and_then(|i| backups.get(i)),
+                Err(_) => backups
+                    .iter()
+                    .find(|b| b.file_name().is_some_and(|name| name == backup.as_str())),
+            };
+            // The list is collected before restoring, because restoring
+            // adds a backup of the current file to it.
+            match chosen {
+                Some(chosen) => {
+                    restore_backup(&opt.data_file, chosen)?;
+                    println!("restored {}", chosen.display());
+                }
+                None => println!("backup {} not found", backup),
+            }
+        }
+        Command::Import { file } => {
+            let cards = parse_vcards(&read_file(file)?);
+            let mut storage = open()?;
+            let mut recs = storage.load(opt.verbose)?;
+            let mut imported = vec![];
+            for card in cards {
+                match card {
+                    Ok(mut record) => {
+                        record.id = recs.next_id();
+                        println!("added {}", record);
+                        imported.push(record.id);
+                        recs.add(record);
+                    }
+                    Err(e) => println!("skipped {}", e),
+                }
+            }
+            println!("imported {} contact(s)", imported.len());
+            if !imported.is_empty() {
+                storage.update(recs, &imported, &[])?;
+            }
+        }
+        Command::Export {
+            file,
+            vcard_version,
+            tag,
+        } => {
+            let recs = open_existing()?.load(opt.verbose)?;
+            let cards: String = recs
+                .into_vec()
+                .iter()
+                .filter(|record| tag_matches(record, &tag))
+                .map(|record| to_vcard(record, vcard_version))
+                .collect();
+            if file == Path::new("-") {
+                print!("{}", cards);
+            } else {
+                fs::write(file, cards)?;
+            }
+        }
+        Command::Migrate {
+            to,
+            to_backend,
+            force,
+        } => {
+            if to.exists() {
+                if !force {
+                    println!(
+                        "{} already exists, use --force to replace its contacts",
+                        to.display()
+                    );
+                    return Ok(());
+                }
+                // Replacing the contacts of the data file with its own
+                // contacts would delete them all when it is a CSV file.
+                if data_file.exists() && fs::canonicalize(&to)? == fs::canonicalize(&data_file)? {
+                    println!("can't migrate a data file to itself");
+                    return Ok(());
                 }
             }
+            let recs = open_existing()?.load(opt.verbose)?;
+            let count = recs.inner.len();
+            let unparsed = recs.unparsed.len();
+            let to_backend = to_backend.unwrap_or_else(|| Backend::for_file(&to));
+            open_storage(&to, Some(to_backend))?.save(recs)?;
+            println!("copied {} contact(s) to {}", count, to.display());
+            // Only CSV files keep the rows that couldn't be loaded.
+            if unparsed > 0 && to_backend != Backend::Csv {
+                println!(
+                    "warning: {} line(s) couldn't be loaded and weren't copied (use -v to see them)",
+                    unparsed
+                );
+            }
         }
+        Command::Tui {} => run_tui(open_existing()?, opt.verbose)?,
     }
     Ok(())
 }
@@ -340,5 +3120,657 @@ fn main() {
     let opt = Opt::from_args();
     if let Err(e) = run(opt) {
         println!("an error occurred: {}", e);
+        std::process::exit(2);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::csv_util::CsvRow;
+    use crate::*;
+
+    fn fields(row: &CsvRow) -> Vec<&str> {
+        row.fields
+            .as_ref()
+            .expect("row should parse")
+            .iter()
+            .map(|f| f.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn check_read_csv_quotes_and_line_endings() {
+        let data = "\u{feff}id,name\r\n1,\"Smith, John\"\r\n\r\n2,\"Say \"\"hi\"\"\",\n3,\"two\r\nlines\"\n4,x";
+        let rows = read_csv(data);
+        assert_eq!(rows.len(), 5);
+        assert_eq!(fields(&rows[0]), vec!["id", "name"]);
+        assert_eq!(fields(&rows[1]), vec!["1", "Smith, John"]);
+        assert_eq!(fields(&rows[2]), vec!["2", "Say \"hi\"", ""]);
+        assert_eq!(fields(&rows[3]), vec!["3", "two\r\nlines"]);
+        assert_eq!(fields(&rows[4]), vec!["4", "x"]);
+        let lines: Vec<_> = rows.iter().map(|row| row.line).collect();
+        assert_eq!(lines, vec![1, 2, 4, 5, 7]);
+    }
+
+    #[test]
+    fn check_read_csv_errors() {
+        let rows = read_csv("1,\"a\"b,c\n2,ok\n3,\"never closed\r\n4,lost");
+        assert_eq!(rows.len(), 4);
+        assert_eq!(
+            rows[0].fields.as_ref().unwrap_err(),
+            &CsvError::TrailingCharacter('b')
+        );
+        assert_eq!(fields(&rows[1]), vec!["2", "ok"]);
+        assert_eq!(rows[2].line, 3);
+        assert_eq!(
+            rows[2].fields.as_ref().unwrap_err(),
+            &CsvError::UnterminatedQuote
+        );
+        assert_eq!(rows[2].text, "3,\"never closed");
+        // The rows after the line with the unclosed quote are still read
+        assert_eq!(rows[3].line, 4);
+        assert_eq!(fields(&rows[3]), vec!["4", "lost"]);
+    }
+
+    #[test]
+    fn check_records_round_trip() {
+        let file = std::env::temp_dir().join("p2_example_test_round_trip.csv");
+        File::create(&file).expect("failed to create test file");
+
+        let mut recs = Records::new();
+        let names = [
+            "Smith, John",
+            "Dwayne \"The Rock\"",
+            "Multi\nLine",
+            " Spaces ",
+        ];
+        for (i, name) in names.iter().enumerate() {
+            let email = Some(format!("{}@example.com", i)).filter(|_| i % 2 == 0);
+            recs.edit(i as i64 + 1, name, email);
+        }
+        save_records(file.clone(), recs).expect("failed to save");
+
+        let loaded = load_records(file, false).expect("failed to load");
+        let loaded = loaded.into_vec();
+        assert_eq!(loaded.len(), names.len());
+        for (rec, name) in loaded.iter().zip(names.iter()) {
+            assert_eq!(&rec.name, name);
+        }
+        assert_eq!(loaded[2].email.as_deref(), Some("2@example.com"));
+        assert_eq!(loaded[3].email, None);
+    }
+
+    #[test]
+    fn check_unparsed_rows_are_kept() {
+        let file = std::env::temp_dir().join("p2_example_test_unparsed.csv");
+        let data = "id,name,email\n1,Ann,a@x.com\n2,Bob,b@x.com,extra\n,No id,\n";
+        let mut recs = parse_records(data.to_owned(), false).expect("header should parse");
+        assert_eq!(recs.unparsed, vec!["2,Bob,b@x.com,extra", ",No id,"]);
+        // Bob's row is kept, so his id can't be given to a new contact.
+        assert_eq!(recs.next_id(), 3);
+        recs.edit(3, "Cy", None);
+        save_records(file.clone(), recs).expect("failed to save");
+
+        let contents = fs::read_to_string(&file).expect("failed to read");
+        assert_eq!(
+            contents,
+            "id,name,email\n1,Ann,a@x.com\n3,Cy,\n2,Bob,b@x.com,extra\n,No id,\n"
+        );
+
+        // A contact without a name couldn't be loaded again, so it isn't saved.
+        let args = ["p2-example", "-d", file.to_str().unwrap(), "add", " "];
+        run(Opt::from_iter(&args)).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), contents);
+        let _ = fs::remove_file(&file);
+    }
+
+    #[test]
+    fn check_header_defines_columns() {
+        let data = "Email,company,ID,Name\na@x.com,Acme,2,Ann\n,,1,Bob\nb@x.com,,3\n";
+        let mut recs = parse_records(data.to_owned(), false).expect("header should parse");
+        assert_eq!(recs.inner.len(), 2);
+        let ann = &recs.inner[&2];
+        assert_eq!(ann.name, "Ann");
+        assert_eq!(ann.email.as_deref(), Some("a@x.com"));
+        assert_eq!(ann.extra["company"], "Acme");
+
+        // Editing keeps the extra columns, and saving keeps the column order.
+        recs.edit(2, "Anne", None);
+        let file = std::env::temp_dir().join("p2_example_test_header.csv");
+        File::create(&file).expect("failed to create test file");
+        save_records(file.clone(), recs).expect("failed to save");
+        let saved = std::fs::read_to_string(file).expect("failed to read");
+        // The row without a name couldn't be loaded, so it is kept as it was.
+        assert_eq!(
+            saved,
+            "email,company,id,name\n,,1,Bob\n,Acme,2,Anne\nb@x.com,,3\n"
+        );
+    }
+
+    #[test]
+    fn check_record_details() {
+        let data = "id,name,phone,tags\n1,Ann,\"work: 555-0100; 555-0199\",Family;friends; family\n2,Bob,x:,\n";
+        let recs = parse_records(data.to_owned(), false).expect("header should parse");
+        assert_eq!(recs.inner.len(), 1, "phone without a number is rejected");
+        let ann = &recs.inner[&1];
+        assert_eq!(ann.phones.len(), 2);
+        assert_eq!(ann.phones[0].label.as_deref(), Some("work"));
+        assert_eq!(ann.phones[1].to_string(), "555-0199");
+        assert_eq!(ann.tags, vec!["Family", "friends"]);
+        assert!(ann.has_tag("FAMILY"));
+        assert!(!ann.has_tag("work"));
+
+        // Columns for new details are added to the file when saving.
+        let mut recs = recs;
+        let ann = recs.get_mut(1).unwrap();
+        ann.address = Some("1 Main St\nSpringfield".to_owned());
+        ann.notes = Some("likes tea".to_owned());
+        let file = std::env::temp_dir().join("p2_example_test_details.csv");
+        File::create(&file).expect("failed to create test file");
+        save_records(file.clone(), recs).expect("failed to save");
+        let saved = std::fs::read_to_string(&file).expect("failed to read");
+        assert!(saved.starts_with("id,name,phone,tags,address,notes\n"));
+
+        let loaded = load_records(file, false).expect("failed to load");
+        let ann = &loaded.inner[&1];
+        assert_eq!(ann.field("phone").unwrap(), "work:555-0100; 555-0199");
+        assert_eq!(ann.address.as_deref(), Some("1 Main St\nSpringfield"));
+        assert_eq!(ann.notes.as_deref(), Some("likes tea"));
+    }
+
+    #[test]
+    fn check_email_and_phone_validation() {
+        for email in [
+            "a@b.co",
+            "first.last+tag@mail.example.org",
+            "x_y@sub-domain.io",
+        ]
+        .iter()
+        {
+            assert!(validate_email(email).is_ok(), "{}", email);
+        }
+        for email in [
+            "",
+            "plain",
+            "@x.com",
+            "a@",
+            "a@b",
+            "a..b@x.com",
+            "a@-x.com",
+            "a b@x.com",
+            "a@x.123",
+            "a@b@x.com",
+        ]
+        .iter()
+        {
+            assert!(validate_email(email).is_err(), "{}", email);
+        }
+        for phone in ["555-0100", "+1 (555) 010.0100", "work:911"].iter() {
+            assert!(parse_phone(phone).is_ok(), "{}", phone);
+        }
+        for phone in ["12", "555-CALL", "1+555", "1234567890123456"].iter() {
+            assert!(parse_phone(phone).is_err(), "{}", phone);
+        }
+    }
+
+    #[test]
+    fn check_validate_report() {
+        let data = "id,name,email,phone\n1,Ann,ann@x.com,555-0100\n2,Bob,bob@,abc\n\nx,Cat,,\n4,,,\n5,\"Dan\"x,,\n";
+        let problems = validate(data);
+        let expected = vec![
+            (3, "email", "invalid email: \"bob@\""),
+            (3, "phone", "invalid phone number: \"abc\""),
+            (
+                5,
+                "id",
+                "id must be a number: invalid digit found in string",
+            ),
+            (6, "name", "missing field: name"),
+            (7, "", "unexpected 'x' after closing quote"),
+        ];
+        let problems: Vec<_> = problems
+            .iter()
+            .map(|(line, field, error)| (*line, field.as_str(), error.as_str()))
+            .collect();
+        assert_eq!(problems, expected);
+
+        // Records with invalid emails are still loaded, so they aren't lost.
+        let recs = parse_records(data.to_owned(), false).unwrap();
+        assert_eq!(recs.inner.len(), 2);
+
+        assert_eq!(
+            validate("id,id\n1"),
+            vec![(
+                1,
+                "header".to_owned(),
+                "header has more than one \"id\" column".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn check_duplicates_and_merge() {
+        assert_eq!(similarity("jon smith", "john smith"), 0.9);
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(normalize_name("Smith,  John"), normalize_name("john smith"));
+        assert_eq!(normalize_email(" Jane+news@X.com"), "jane@x.com");
+
+        let mut recs = Records::new();
+        recs.edit(7, "Jane Doe", None);
+        recs.edit(3, "Doe, Jane", Some("jane@x.com".to_owned()));
+        recs.edit(9, "J. Doe", Some("JANE@x.com".to_owned()));
+        recs.edit(4, "Bob Stone", None);
+        recs.edit(5, "Rob Stone", Some("rob@x.com".to_owned()));
+        recs.edit(6, "Someone Else", None);
+        assert_eq!(recs.duplicates(0.85), vec![vec![3, 7, 9], vec![4, 5]]);
+        assert_eq!(recs.duplicates(0.95), vec![vec![3, 7, 9]]);
+
+        let phone = |p: &str| p.parse::<Phone>().unwrap();
+        let jane = recs.get_mut(7).unwrap();
+        jane.phones = vec![phone("555-0100")];
+        jane.tags = vec!["Work".to_owned()];
+        jane.notes = Some("met at work".to_owned());
+        let jane = recs.get_mut(9).unwrap();
+        jane.phones = vec![phone("home:555-0199"), phone("555-0100")];
+        jane.tags = vec!["work".to_owned(), "friend".to_owned()];
+        jane.address = Some("1 Main St".to_owned());
+
+        assert_eq!(recs.merge(&[9, 7, 3]), Some(3));
+        assert_eq!(recs.inner.len(), 4);
+        let jane = &recs.inner[&3];
+        assert_eq!(jane.name, "Doe, Jane");
+        assert_eq!(jane.email.as_deref(), Some("jane@x.com"));
+        assert_eq!(jane.field("phone").unwrap(), "555-0100; home:555-0199");
+        assert_eq!(jane.tags, vec!["Work", "friend"]);
+        assert_eq!(jane.address.as_deref(), Some("1 Main St"));
+        assert_eq!(jane.notes.as_deref(), Some("met at work"));
+        assert_eq!(recs.merge(&[100]), None);
+    }
+
+    #[test]
+    fn check_query_parsing() {
+        let query: Query = r#"email:@msu.edu  Val name:"van der" tag:x "a:b""#.parse().unwrap();
+        let terms: Vec<_> = query
+            .terms
+            .iter()
+            .map(|t| (t.field.as_deref(), t.value.as_str()))
+            .collect();
+        assert_eq!(
+            terms,
+            vec![
+                (Some("email"), "@msu.edu"),
+                (None, "val"),
+                (Some("name"), "van der"),
+                (Some("tags"), "x"),
+                (None, "a:b"),
+            ]
+        );
+        assert_eq!(
+            "name:\"x".parse::<Query>(),
+            Err(QueryError::UnterminatedQuote)
+        );
+    }
+
+    #[test]
+    fn check_search_ranking() {
+        let data = "id,name,email,phone,company\n\
+                    1,Val Howman,vhowman3@mediafire.com,555-0100,Acme\n\
+                    2,Terrie Petraitis,tpetraitis2@msu.edu,,\n\
+                    3,Valentina Lopez,vlopez@msu.edu,,Initech\n\
+                    4,Jonh Smith,js@x.com,,\n\
+                    5,John Smith,john@x.com,,\n\
+                    6,Vall Msu,other@x.com,,\n";
+        let recs = parse_records(data.to_owned(), false).unwrap();
+        let search = |query: &str| -> Vec<i64> {
+            let query = query.parse().unwrap();
+            recs.search(&query)
+                .unwrap()
+                .iter()
+                .map(|rec| rec.id)
+                .collect()
+        };
+        assert_eq!(search("email:@msu.edu"), vec![2, 3]);
+        assert_eq!(search("email:@msu.edu name:val"), vec![3]);
+        assert_eq!(search("msu"), vec![2, 3, 6]);
+        assert_eq!(search("val"), vec![1, 3, 6]);
+        // Exact words rank above typos, which are still found.
+        assert_eq!(search("john"), vec![5, 4]);
+        assert_eq!(search("smiht"), vec![4, 5]);
+        assert_eq!(search("phone:5550100"), vec![1]);
+        assert_eq!(search("company:initech"), vec![3]);
+        assert_eq!(search("id:2"), vec![2]);
+        assert_eq!(search("nobody"), Vec::<i64>::new());
+        let query = "emial:x".parse().unwrap();
+        assert_eq!(
+            recs.search(&query).unwrap_err(),
+            QueryError::UnknownField("emial".to_owned())
+        );
+
+        let mut sorted: Vec<_> = recs.inner.values().collect();
+        sorted.sort_by(|a, b| SortKey::Name.compare(a, b));
+        let ids: Vec<_> = sorted.iter().map(|rec| rec.id).collect();
+        assert_eq!(ids, vec![5, 4, 2, 1, 3, 6]);
+    }
+
+    #[test]
+    fn check_saves_keep_backups() {
+        let dir = std::env::temp_dir().join("p2_example_test_backups");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("failed to create test dir");
+        let file = dir.join("contacts.csv");
+
+        // The first save has nothing to back up.
+        let mut recs = Records::new();
+        recs.edit(1, "Ann", None);
+        save_records(file.clone(), recs).expect("failed to save");
+        assert!(list_backups(&file).unwrap().is_empty());
+
+        for i in 2..=BACKUPS_TO_KEEP as i64 + 3 {
+            let mut recs = load_records(file.clone(), false).unwrap();
+            recs.edit(i, "Bob", None);
+            save_records(file.clone(), recs).expect("failed to save");
+        }
+        assert!(!temp_path(&file).exists());
+        let backups = list_backups(&file).unwrap();
+        assert_eq!(backups.len(), BACKUPS_TO_KEEP);
+        // The newest backup has the contents from before the last save.
+        let newest = load_records(backups[0].clone(), false).unwrap();
+        assert_eq!(newest.inner.len(), BACKUPS_TO_KEEP + 2);
+
+        restore_backup(&file, &backups[BACKUPS_TO_KEEP - 1]).expect("failed to restore");
+        assert_eq!(load_records(file.clone(), false).unwrap().inner.len(), 3);
+        // Restoring backed up the file it replaced.
+        let latest = load_records(list_backups(&file).unwrap()[0].clone(), false).unwrap();
+        assert_eq!(latest.inner.len(), BACKUPS_TO_KEEP + 3);
+
+        // A file in the way of the backup directory makes pruning fail. The
+        // first save of a new file doesn't need a backup, so it still works.
+        let other = dir.join("other.csv");
+        fs::write(backup_dir(&other), "").unwrap();
+        let mut recs = Records::new();
+        recs.edit(1, "Ann", None);
+        save_records(other.clone(), recs).expect("pruning shouldn't fail the save");
+        assert!(list_backups(&other).is_err());
+        assert_eq!(load_records(other, false).unwrap().inner.len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn check_list_options() {
+        let data =
+            "id,name,email,tags\n1,Cy,cy@x.com,work\n2,Ann,,\n3,Bob,bob@x.com,work\n4,Dee,,work\n";
+        let recs = parse_records(data.to_owned(), false).unwrap();
+        let list = |args: &[&str]| -> Vec<i64> {
+            let args = ["list"].iter().chain(args.iter());
+            let options = ListOptions::from_iter_safe(args).expect("bad options");
+            recs.list(&options).iter().map(|rec| rec.id).collect()
+        };
+        assert_eq!(list(&[]), vec![1, 2, 3, 4]);
+        assert_eq!(list(&["-s", "name"]), vec![2, 3, 1, 4]);
+        assert_eq!(list(&["--sort", "email", "--reverse"]), vec![4, 2, 1, 3]);
+        assert_eq!(list(&["-s", "name", "-o", "1", "-l", "2"]), vec![3, 1]);
+        assert_eq!(list(&["-o", "9"]), Vec::<i64>::new());
+        assert_eq!(list(&["--has-email", "-t", "work"]), vec![1, 3]);
+        assert_eq!(list(&["--missing-email", "-r"]), vec![4, 2]);
+        assert!(ListOptions::from_iter_safe(&["list", "--has-email", "--missing-email"]).is_err());
+        // Listing doesn't take the records out of the collection.
+        assert_eq!(recs.inner.len(), 4);
+    }
+
+    #[test]
+    fn check_sqlite_storage() {
+        let dir = std::env::temp_dir().join("p2_example_test_sqlite");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("failed to create test dir");
+        let csv = dir.join("contacts.csv");
+        let db = dir.join("contacts.db");
+        assert_eq!(Backend::for_file(&db), Backend::Sqlite);
+        assert_eq!(Backend::for_file(&csv), Backend::Csv);
+
+        // The columns are in an unusual order and include one this program
+        // doesn't know, which has to survive the trip through the database.
+        let data = "name,Company,id,phone\nAnn,Acme,1,work:555-0100; 555-0199\nBob,,2,\n";
+        fs::write(&csv, data).unwrap();
+        let recs = open_storage(&csv, None).unwrap().load(false).unwrap();
+        let mut storage = open_storage(&db, None).unwrap();
+        storage.save(recs).expect("failed to save");
+
+        let mut recs = storage.load(false).unwrap();
+        assert_eq!(recs.header.columns, vec!["name", "Company", "id", "phone"]);
+        assert_eq!(recs.inner[&1].extra["Company"], "Acme");
+        assert_eq!(recs.inner[&1].phones.len(), 2);
+
+        // Only the changed records are written, and the header gets the
+        // email column the new record needs.
+        recs.remove(2);
+        recs.add(Record::new(3, "Cy", Some("cy@x.com".to_owned())));
+        storage.update(recs, &[3], &[2]).expect("failed to update");
+        let recs = open_storage(&db, None).unwrap().load(false).unwrap();
+        let mut ids: Vec<_> = recs.inner.keys().copied().collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![1, 3]);
+        assert_eq!(recs.inner[&3].email.as_deref(), Some("cy@x.com"));
+
+        let back = dir.join("back.csv");
+        open_storage(&back, None).unwrap().save(recs).unwrap();
+        assert_eq!(
+            fs::read_to_string(&back).unwrap(),
+            "name,Company,id,phone,email\nAnn,Acme,1,work:555-0100; 555-0199,\nCy,,3,,cy@x.com\n"
+        );
+
+        // Databases are validated by checking the records loaded from them.
+        let mut recs = open_storage(&db, None).unwrap().load(false).unwrap();
+        recs.edit(1, "Ann", Some("ann@".to_owned()));
+        assert_eq!(
+            validate_records(&recs),
+            vec![(1, "email".to_owned(), "invalid email: \"ann@\"".to_owned())]
+        );
+
+        // Commands that only read don't create a database that isn't there.
+        let missing = dir.join("mistyped.db");
+        for cmd in ["list", "search", "export", "validate"].iter() {
+            let args = ["p2-example", "-d", missing.to_str().unwrap(), cmd, "x"];
+            let args = if *cmd == "list" || *cmd == "validate" {
+                &args[..4]
+            } else {
+                &args[..]
+            };
+            let err = run(Opt::from_iter(args)).expect_err(cmd);
+            assert_eq!(err.kind(), std::io::ErrorKind::NotFound, "{}", cmd);
+        }
+        assert!(!missing.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn check_tui_keys() {
+        let dir = std::env::temp_dir().join("p2_example_test_tui");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("failed to create test dir");
+        let file = dir.join("contacts.csv");
+        let data = "id,name,email,Company\n1,Val Howman,val@x.com,Acme\n2,Ann Lee,,\n3,Bob Ray,bob@y.org,\n";
+        fs::write(&file, data).unwrap();
+        let storage = open_storage(&file, None).unwrap();
+        let records = storage.load(false).unwrap();
+        let mut app = App::new(storage, records);
+        let press = |app: &mut App, keys: &str| {
+            for c in keys.chars() {
+                let key = match c {
+                    '\n' => KeyCode::Enter,
+                    '\t' => KeyCode::Tab,
+                    '\u{1b}' => KeyCode::Esc,
+                    '\u{8}' => KeyCode::Backspace,
+                    c => KeyCode::Char(c),
+                };
+                assert!(!app.handle_key(key.into()).unwrap(), "quit on {:?}", c);
+            }
+        };
+        assert_eq!(app.shown, vec![1, 2, 3]);
+        // Sorting keeps the same record selected.
+        press(&mut app, "s");
+        assert_eq!(app.shown, vec![2, 3, 1]);
+        assert_eq!(app.selected().unwrap().id, 1);
+        press(&mut app, "k");
+        assert_eq!(app.selected().unwrap().id, 3);
+
+        // The list is searched as the query is typed.
+        press(&mut app, "/x.co");
+        assert_eq!(app.shown, vec![1]);
+        press(&mut app, "\u{8}\u{8}\u{8}\u{8}");
+        assert_eq!(app.shown, vec![2, 3, 1]);
+        press(&mut app, "email:\n\u{1b}");
+        assert_eq!(app.query, "");
+
+        // A bad email keeps the form open. Editing keeps the extra column.
+        press(&mut app, "/val\ne\t\u{8}\u{8}\u{8}\n");
+        assert!(matches!(app.mode, Mode::Form(_)));
+        press(&mut app, "com\t555-0100\n");
+        assert!(matches!(app.mode, Mode::Browse), "{}", app.status);
+        let saved = load_records(file.clone(), false).unwrap();
+        assert_eq!(saved.inner[&1].email.as_deref(), Some("val@x.com"));
+        assert_eq!(saved.inner[&1].phones.len(), 1);
+        assert_eq!(saved.inner[&1].extra["Company"], "Acme");
+
+        // Removing asks first.
+        press(&mut app, "\u{1b}dn");
+        assert_eq!(load_records(file.clone(), false).unwrap().inner.len(), 3);
+        let id = app.selected().unwrap().id;
+        press(&mut app, "dy");
+        assert!(!load_records(file.clone(), false)
+            .unwrap()
+            .inner
+            .contains_key(&id));
+
+        // When saving fails the error is shown and the form stays open, so
+        // the save can be tried again. A directory where the temporary file
+        // goes makes the save fail.
+        let temp = temp_path(&file);
+        fs::create_dir(&temp).unwrap();
+        press(&mut app, "aZed\tzed@x.com\n");
+        assert!(
+            app.status.starts_with("an error occurred"),
+            "{}",
+            app.status
+        );
+        assert!(matches!(app.mode, Mode::Form(_)));
+        fs::remove_dir(&temp).unwrap();
+        press(&mut app, "\n");
+        assert_eq!(app.status, "record saved");
+        let saved = load_records(file, false).unwrap();
+        assert_eq!(saved.inner[&4].name, "Zed");
+        assert_eq!(app.selected().unwrap().id, 4);
+        assert!(app.handle_key(KeyCode::Char('q').into()).unwrap());
+    }
+
+    #[test]
+    fn check_vcard_round_trip() {
+        let mut jane = Record::new(1, "Jane van Doe", Some("jane@x.com".to_owned()));
+        jane.phones = vec![
+            "work:555-0100".parse().unwrap(),
+            "555-0199".parse().unwrap(),
+        ];
+        jane.address = Some("1 Main St\nSpringfield, IL".to_owned());
+        jane.tags = vec!["Work".to_owned(), "a,b".to_owned()];
+        jane.notes = Some(
+            "Ünïcode notes; long enough to be folded. "
+                .repeat(4)
+                .trim_end()
+                .to_owned(),
+        );
+        let bob = Record::new(2, "Bob", None);
+
+        for version in [VcardVersion::V3, VcardVersion::V4].iter() {
+            let data = to_vcard(&jane, *version) + &to_vcard(&bob, *version);
+            assert!(data.lines().all(|line| line.len() <= VCARD_LINE_LENGTH + 1));
+            assert!(data.contains("\r\n "), "long notes are folded");
+
+            let cards: Vec<_> = parse_vcards(&data)
+                .into_iter()
+                .map(|c| c.unwrap())
+                .collect();
+            assert_eq!(cards.len(), 2);
+            let (card, original) = (&cards[0], &jane);
+            assert_eq!(card.name, original.name);
+            assert_eq!(card.email, original.email);
+            assert_eq!(card.phones, original.phones);
+            assert_eq!(card.address, original.address);
+            assert_eq!(card.tags, original.tags);
+            assert_eq!(card.notes, original.notes);
+            assert_eq!(cards[1].name, "Bob");
+            assert_eq!(cards[1].email, None);
+        }
+    }
+
+    #[test]
+    fn check_vcard_import() {
+        let data = "BEGIN:VCARD\r\nVERSION:4.0\r\nN:Smith;John;Q.;Dr.;\r\n\
+                    EMAIL;TYPE=home:john@home.com\r\nitem1.EMAIL;PREF=1:john@wo\r\n rk.com\r\n\
+                    TEL;VALUE=uri;TYPE=\"cell,voice\":tel:+1-555-0100\r\n\
+                    TEL;TYPE=VOICE:555-0199\r\nADR;TYPE=home:;;12 Oak St;Town;;12345;USA\r\n\
+                    CATEGORIES:friends,golf\\, club\r\nEND:VCARD\r\n\
+                    BEGIN:VCARD\nVERSION:3.0\nEMAIL:nobody@x.com\nEND:VCARD\n\
+                    BEGIN:VCARD\nVERSION:2.1\nFN:Old Style\nTEL;WORK;VOICE:555-0111\n";
+        let cards = parse_vcards(data);
+        assert_eq!(cards.len(), 3);
+        let john = cards[0].as_ref().unwrap();
+        assert_eq!(john.name, "Dr. John Q. Smith");
+        assert_eq!(john.email.as_deref(), Some("john@work.com"));
+        assert_eq!(john.notes.as_deref(), Some("Other email: john@home.com"));
+        assert_eq!(john.field("phone").unwrap(), "cell:+1-555-0100; 555-0199");
+        assert_eq!(john.address.as_deref(), Some("12 Oak St\nTown\n12345\nUSA"));
+        assert_eq!(john.tags, vec!["friends", "golf, club"]);
+        assert_eq!(cards[1].as_ref().unwrap_err(), &VcardError::MissingName(2));
+        assert_eq!(cards[2].as_ref().unwrap_err(), &VcardError::MissingEnd(3));
+    }
+
+    #[test]
+    fn check_output_formats() {
+        let data = "id,name,email,company\n2,Zoë \"Z\" Ng,,Acme\n10,Bob,bob@x.com,\n";
+        let mut recs = parse_records(data.to_owned(), false).unwrap();
+        let bob = recs.get_mut(10).unwrap();
+        bob.phones = vec!["work:555-0100".parse().unwrap()];
+        bob.notes = Some("line 1\nline 2".to_owned());
+        let mut records: Vec<_> = recs.inner.values().collect();
+        records.sort_by_key(|rec| rec.id);
+        let header = recs.header_for_saving();
+        let format = |format| format_records(&records, format, &header);
+
+        assert_eq!(
+            format(OutputFormat::Table),
+            "ID  NAME        EMAIL      PHONE          NOTES\n\
+             --  ----------  ---------  -------------  ---------------\n\
+             2   Zoë \"Z\" Ng\n\
+             10  Bob         bob@x.com  work:555-0100  line 1 / line 2\n"
+        );
+        let zoe = r#"{"id":2,"name":"Zoë \"Z\" Ng","email":null,"phones":[],"address":null,"tags":[],"notes":null,"extra":{"company":"Acme"}}"#;
+        let bob = r#"{"id":10,"name":"Bob","email":"bob@x.com","phones":[{"label":"work","number":"555-0100"}],"address":null,"tags":[],"notes":"line 1\nline 2","extra":{}}"#;
+        assert_eq!(
+            format(OutputFormat::JsonLines),
+            format!("{}\n{}\n", zoe, bob)
+        );
+        assert_eq!(
+            format(OutputFormat::Json),
+            format!("[\n  {},\n  {}\n]\n", zoe, bob)
+        );
+        assert_eq!(
+            format(OutputFormat::Csv),
+            "id,name,email,company,phone,notes\n\
+             2,\"Zoë \"\"Z\"\" Ng\",,Acme,,\n\
+             10,Bob,bob@x.com,,work:555-0100,\"line 1\nline 2\"\n"
+        );
+        assert_eq!(format_records(&[], OutputFormat::Json, &header), "[]\n");
+        assert_eq!(json_string("tab\tbell\u{7}"), r#""tab\tbell\u0007""#);
+    }
+
+    #[test]
+    fn check_header_errors() {
+        let parse = |data: &str| parse_records(data.to_owned(), false).map(|recs| recs.inner.len());
+        assert_eq!(parse("1,Ann,\n2,Bob,b@x.com").unwrap(), 2);
+        assert_eq!(parse("id,name,notes\n1,Ann,,extra").unwrap(), 0);
+        assert!(
+            matches!(parse("id,email\n1,a@x.com"), Err(ParseError::MissingColumn(c)) if c == "name")
+        );
+        assert!(matches!(parse("id,name,ID"), Err(ParseError::DuplicateColumn(c)) if c == "id"));
+        assert!(matches!(parse("id,name,"), Err(ParseError::UnnamedColumn)));
     }
 }
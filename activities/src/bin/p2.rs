@@ -29,7 +29,7 @@
 // - String.split('\n').enumerate() is handy to get an index + value
 
 
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::path::PathBuf;
 use std::io::prelude::*;
 use std::collections::HashMap;
@@ -48,6 +48,10 @@ struct Opt {
     #[structopt(short, parse(from_os_str), default_value = "src/bin/p2_data.csv")]
     file_path: PathBuf,
 
+    /// Show the lines in the file that couldn't be parsed
+    #[structopt(short, long)]
+    verbose: bool,
+
     #[structopt(subcommand)]
     cmd: Command,
 }
@@ -55,7 +59,30 @@ struct Opt {
 #[derive(StructOpt, Debug)]
 enum Command {
     // NOTE subcommands have to be structures (e.g., List {})
+    /// List all contacts
     List {},
+    /// Add a new contact
+    Add {
+        name: String,
+        #[structopt(short, long)]
+        email: Option<String>,
+    },
+    /// Change the name and/or email of a contact. An empty email removes it
+    Edit {
+        id: i64,
+        #[structopt(short, long)]
+        name: Option<String>,
+        #[structopt(short, long)]
+        email: Option<String>,
+    },
+    /// Remove a contact
+    Remove {
+        id: i64,
+    },
+    /// Search for contacts whose name or email contains the query
+    Search {
+        query: String,
+    },
 }
 
 
@@ -81,11 +108,46 @@ impl RecordsMap {
         self.inner.insert(record.id.to_string(), record); // { 1: Record {} }
     }
 
-    fn get_contact_by_id(self, id: &str) {
-        match self.inner.get(&id.to_owned()) {
-            Some(record) => println!("record={:?}",record),
-            None => println!("Contact not found"),
+    // UPDATE Borrow self instead of taking ownership, otherwise the RecordsMap
+    // is gone after looking up a single contact
+    fn get_contact_by_id(&self, id: i64) -> Option<&Record> {
+        self.inner.get(&id.to_string())
+    }
+
+    // Edit an existing Record. Only the fields that are Some get changed.
+    // NOTE An email of Some("") removes the email, since it's optional
+    // Returns false if there's no Record with that id
+    fn edit(&mut self, id: i64, name: Option<String>, email: Option<String>) -> bool {
+        // NOTE get_mut() gives back Option<&mut Record> so we can change it in place
+        let record = match self.inner.get_mut(&id.to_string()) {
+            Some(record) => record,
+            None => return false,
+        };
+        if let Some(name) = name {
+            record.name = name;
         }
+        if let Some(email) = email {
+            record.email = Some(email).filter(|e| !e.is_empty());
+        }
+        true
+    }
+
+    fn remove(&mut self, id: i64) -> Option<Record> {
+        self.inner.remove(&id.to_string())
+    }
+
+    // Find all Records whose name or email contains the query, ignoring case
+    fn search(&self, query: &str) -> Vec<&Record> {
+        let query = query.to_lowercase();
+        let mut results: Vec<&Record> = self.inner
+            .values()
+            .filter(|r| {
+                r.name.to_lowercase().contains(&query)
+                    || r.email.as_ref().is_some_and(|e| e.to_lowercase().contains(&query))
+            })
+            .collect();
+        results.sort_by_key(|r| r.id);
+        results
     }
 
     fn into_vec(mut self) -> Vec<Record> {
@@ -100,13 +162,29 @@ impl RecordsMap {
         records
     }
 
-    fn compute_next_id(&self) -> i64 {
+    // UPDATE Also takes the rows that couldn't be parsed, since they are kept
+    // in the file. Their ids are still taken, so a new contact can't reuse one
+    fn compute_next_id(&self, unparsed: &[String]) -> i64 {
         // Helper function to get the next id number to assign to new Record
-        // TODO
         // Find out the current last key value number in this HM
         // Convert that string to a i64
         // Compute the next id value by +1 and return it
-
+        // NOTE Keys are Strings so "99" > "100". Have to convert BEFORE finding
+        // the max, otherwise the wrong id comes back
+        // NOTE Every key came from a Record's i64 id, so parse() never fails here
+        let last_id = self.inner.keys().filter_map(|k| k.parse::<i64>().ok());
+        // NOTE A bad row may not even have a proper id, so only the digits at
+        // the start of the row count. E.g. "12x,Bob," still takes id 12
+        let unparsed_ids = unparsed.iter().filter_map(|text| {
+            let digits: String = text.chars().take_while(|c| c.is_ascii_digit()).collect();
+            digits.parse::<i64>().ok()
+        });
+        let last_id = last_id.chain(unparsed_ids).max();
+        match last_id {
+            Some(id) => id + 1,
+            // No contacts yet so start counting at 1
+            None => 1,
+        }
     }
 }
 
@@ -132,8 +210,20 @@ enum ParseError {
     MissingRequiredField, 
 }
 
+// NOTE Splitting on ',' breaks as soon as a name has a comma in it, e.g.
+// "Smith, John". CSV (RFC 4180) handles that by wrapping the field in quotes:
+// "Smith, John". A quote inside a quoted field is written twice: "Say ""hi""".
+// Quoted fields can even contain line breaks, so we can't split on '\n' first.
+// UPDATE Moved the CSV reading/writing into common/csv_util.rs so p2-example
+// can use the same code
+#[path = "common/csv_util.rs"]
+mod csv_util;
+use csv_util::{csv_row, read_csv};
+
 
-fn load_records(file_path: PathBuf) -> std::io::Result<RecordsMap> {
+// UPDATE Also returns the raw text of the rows that couldn't be parsed so
+// they can be written back to the file as-is instead of being lost on save
+fn load_records(file_path: &PathBuf, verbose: bool) -> std::io::Result<(RecordsMap, Vec<String>)> {
     // Q: How do you slowly debug/iterate when dealing with returning
     // certain types with functions? E.g., I just want to test out
     // how to read the file and parse line-by-line. In order to do that,
@@ -146,21 +236,37 @@ fn load_records(file_path: PathBuf) -> std::io::Result<RecordsMap> {
     file.read_to_string(&mut contents)?;
     // println!("contents = {:?}", contents);
     // 3. Everything good to this point, return our Ok variant RecordsMap
-    Ok(parse_records(contents))
+    Ok(parse_records(contents, verbose))
 }
 
-fn parse_records(records: String) -> RecordsMap {
+fn parse_records(records: String, verbose: bool) -> (RecordsMap, Vec<String>) {
     let mut records_map = RecordsMap::new();
+    let mut unparsed = Vec::new();
 
     // NOTE Use for loop w/ String.split().enumerate() for less temp vars
-    for (i, record) in records.split('\n').enumerate() {
-        // NOTE Only check the TRUE condition to save code
-        if record != "" {
-            match parse_record(record) {
-                Ok(r) => {
-                    records_map.add(r);
+    // UPDATE Switched to read_csv() so quoted fields with commas/newlines work.
+    // It also skips the blank lines for us
+    for row in read_csv(&records) {
+        // NOTE The first line is the "id,name,email" header, not a contact
+        if row.line == 1 && row.text.starts_with("id,") {
+            continue;
+        }
+        // NOTE Converting both errors to String so one match can handle them
+        let result = match row.fields {
+            Ok(fields) => parse_record(&fields).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match result {
+            Ok(r) => {
+                records_map.add(r);
+            }
+            Err(e) => {
+                // NOTE Only show the bad lines when asked with -v so they don't
+                // clutter up the output of every command
+                if verbose {
+                    println!("error on line {}: {}\n  >> \"{}\"\n", row.line, e, row.text);
                 }
-                Err(e) => { println!("error on line {}: {}\n  >> \"{}\"\n", i + 1, e, record); }
+                unparsed.push(row.text);
             }
         }
     }
@@ -207,13 +313,46 @@ fn parse_records(records: String) -> RecordsMap {
     // }
 
     // Return complete RecordsMap
-    println!("Final parsed RecordsMap = {:?}", records_map);
-    records_map
+    // println!("Final parsed RecordsMap = {:?}", records_map);
+    (records_map, unparsed)
 }
 
-fn parse_record(record: &str) -> Result<Record, ParseError> {
+// UPDATE The rows that couldn't be parsed are written back unchanged at the end
+// of the file, so fixing them by hand is still possible after a save
+fn save_records(file_path: &PathBuf, records_map: RecordsMap, unparsed: &[String]) -> std::io::Result<()> {
+    // NOTE Truncate so the whole file gets rewritten with the current contacts
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(file_path)?;
+
+    file.write_all(csv_row(&["id", "name", "email"]).as_bytes())?;
+    // NOTE into_vec() sorts by id so the file stays in order
+    for record in records_map.into_vec() {
+        let email = record.email.unwrap_or_default();
+        let line = csv_row(&[&record.id.to_string(), &record.name, &email]);
+        file.write_all(line.as_bytes())?;
+    }
+    for text in unparsed {
+        file.write_all(format!("{}\n", text).as_bytes())?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+// Saves and lets the user know about any lines that couldn't be parsed
+fn save_and_report(file_path: &PathBuf, records_map: RecordsMap, unparsed: &[String]) -> std::io::Result<()> {
+    if !unparsed.is_empty() {
+        println!("warning: {} line(s) couldn't be parsed and were kept at the end of the file (use -v to see them)", unparsed.len());
+    }
+    save_records(file_path, records_map, unparsed)
+}
+
+// UPDATE Takes the fields from read_csv() instead of splitting the line itself
+fn parse_record(fields: &[String]) -> Result<Record, ParseError> {
     // E.g.: "5,Gaylon A.,", "", "7,Ash,a@email.com"
-    let fields: Vec<&str> = record.split(',').collect();
+    // let fields: Vec<&str> = record.split(',').collect();
 
     // NOTE Use the Vec.get() method but along w/ match since get() -> Option
     // My original attempt used .get().unwrap_or_else(|| &"default_id")
@@ -221,7 +360,7 @@ fn parse_record(record: &str) -> Result<Record, ParseError> {
     // a good practice. We're giving it a couple places to fail before finally
     // assigning our variable a value. This makes it more robust. When you
     // use expressions, this allows the compiler to check all possible use cases.
-    let id = match fields.get(0) {
+    let id = match fields.first() {
         // Q: How to convert a &str to i64?
         // A: Solution used i64::from_str_radix(id, 10)?
         // A: Quite a few options: https://stackoverflow.com/questions/27043268/convert-a-string-to-int?rq=1
@@ -229,14 +368,15 @@ fn parse_record(record: &str) -> Result<Record, ParseError> {
         // NOTE I need to ensure that my custom error impl the 'From' trait
         // from the ParseIntError i.e.: #[from] std::num::ParseIntError
         // NOTE These are TWO different error types!
-        Some(id) => i64::from_str_radix(id, 10)?,
+        // UPDATE parse() does the same thing as from_str_radix(id, 10)
+        Some(id) => id.parse::<i64>()?,
         // NOTE It's here that we can return early our custom error.
         None => return Err(ParseError::EmptyRecord),
     };
 
     // NOTE Without chaining the .filter() we end up with records that
     // contain an empty string. Better to remove them from final list
-    let name = match fields.get(1).filter(|n| **n != "") {
+    let name = match fields.get(1).filter(|n| !n.is_empty()) {
         // Q: What's the difference between to_owned() vs to_string()?
         // Only when I use to_string() does it not error...
         Some(name) => name.to_string(), // Error if I use to_owned()
@@ -248,14 +388,16 @@ fn parse_record(record: &str) -> Result<Record, ParseError> {
     // The difference between approaches is that the solution will return None
     // instead of an empty string for the 'email' field.
     // I still need to handle getting email into a String type for Record struct
-    let email = fields.get(2).unwrap_or_else(|| &"").to_string();
-    // let email = fields.get(2).map(|e| e.to_string()).filter(|e| e != "");
+    // let email = fields.get(2).unwrap_or_else(|| &"").to_string();
+    // UPDATE Switched to the solution's approach, otherwise every contact without
+    // an email ends up with Some("") which is annoying to check for everywhere
+    let email = fields.get(2).map(|e| e.to_string()).filter(|e| !e.is_empty());
 
     // Return our completed Record
     let new_record = Record {
         id,
         name,
-        email: Some(email)
+        email
     };
 
     Ok(new_record)
@@ -267,7 +409,7 @@ fn run(opt: Opt) -> Result<(), std::io::Error> {
         // Can't simply use struct variant ie. Command::List =>
         // NOTE To ignore everything inside the List we use 'List { .. }' syntax
         Command::List {..} => {
-            let records_map = load_records(opt.file_path)?;
+            let (records_map, _) = load_records(&opt.file_path, opt.verbose)?;
             // UPDATE Now drain() and convert to Vec for sorted list
             // let records_vec = records_map.into_vec();
             // NOTE The into_vec() method completely drops our RecordsMap
@@ -278,6 +420,58 @@ fn run(opt: Opt) -> Result<(), std::io::Error> {
                 println!("record={:?}", record);
             }
         }
+        Command::Add { name, email } => {
+            // NOTE Same as edit, a contact without a name couldn't be loaded again
+            let name = name.trim().to_string();
+            if name.is_empty() {
+                println!("name can't be empty");
+                return Ok(());
+            }
+            let (mut records_map, unparsed) = load_records(&opt.file_path, opt.verbose)?;
+            let id = records_map.compute_next_id(&unparsed);
+            let email = email.filter(|e| !e.is_empty());
+            records_map.add(Record { id, name, email });
+            println!("added contact {}", id);
+            save_and_report(&opt.file_path, records_map, &unparsed)?;
+        }
+        Command::Edit { id, name, email } => {
+            // NOTE Names are required, so don't allow editing one to be empty
+            let name = name.map(|n| n.trim().to_string());
+            if name.as_deref() == Some("") {
+                println!("name can't be empty");
+                return Ok(());
+            }
+            let (mut records_map, unparsed) = load_records(&opt.file_path, opt.verbose)?;
+            if !records_map.edit(id, name, email) {
+                println!("Contact not found");
+                return Ok(());
+            }
+            if let Some(record) = records_map.get_contact_by_id(id) {
+                println!("record={:?}", record);
+            }
+            save_and_report(&opt.file_path, records_map, &unparsed)?;
+        }
+        Command::Remove { id } => {
+            let (mut records_map, unparsed) = load_records(&opt.file_path, opt.verbose)?;
+            match records_map.remove(id) {
+                Some(record) => println!("removed record={:?}", record),
+                None => {
+                    println!("Contact not found");
+                    return Ok(());
+                }
+            }
+            save_and_report(&opt.file_path, records_map, &unparsed)?;
+        }
+        Command::Search { query } => {
+            let (records_map, _) = load_records(&opt.file_path, opt.verbose)?;
+            let results = records_map.search(&query);
+            if results.is_empty() {
+                println!("No contacts found");
+            }
+            for record in results {
+                println!("record={:?}", record);
+            }
+        }
     }
     Ok(())
 }
@@ -311,3 +505,43 @@ fn main() {
     }
 
 }
+
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn check_quoted_fields_round_trip() {
+        let names = ["Smith, John", "Say \"hi\"", "two\r\nlines", "plain"];
+        let mut data = String::from("\u{feff}id,name,email\r\n\r\n");
+        for (i, name) in names.iter().enumerate() {
+            data.push_str(&csv_row(&[&(i + 1).to_string(), name, ""]));
+        }
+        data.push_str("9,\"never closed\n");
+
+        let (records_map, unparsed) = parse_records(data, false);
+        assert_eq!(unparsed, vec!["9,\"never closed"]);
+        let records = records_map.into_vec();
+        assert_eq!(records.len(), names.len());
+        for (record, name) in records.iter().zip(names.iter()) {
+            assert_eq!(&record.name, name);
+            assert_eq!(record.email, None);
+        }
+    }
+
+    #[test]
+    fn check_unparsed_rows_are_kept() {
+        let file_path = std::env::temp_dir().join("p2_test_unparsed.csv");
+        let data = "id,name,email\n1,Ash,a@email.com\nx,bad id,\n3,,c@email.com\n";
+        let (records_map, unparsed) = parse_records(data.to_string(), false);
+        save_records(&file_path, records_map, &unparsed).unwrap();
+
+        let (records_map, unparsed) = load_records(&file_path, false).unwrap();
+        assert_eq!(unparsed, vec!["x,bad id,", "3,,c@email.com"]);
+        // The kept rows' ids are taken too, so a new contact doesn't reuse 3
+        assert_eq!(records_map.compute_next_id(&unparsed), 4);
+        assert_eq!(records_map.into_vec().len(), 1);
+        let _ = std::fs::remove_file(&file_path);
+    }
+}